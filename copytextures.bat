@@ -1,2 +0,0 @@
-mkdir ./target/debug/textures/
-cp ./src/textures/* ./target/debug/textures/
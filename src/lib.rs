@@ -2,209 +2,4743 @@ use std::env;
 use std::ffi::c_void;
 
 #[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ShaderType {
     VertexShader = gl::VERTEX_SHADER,
     FragmentShader = gl::FRAGMENT_SHADER,
+    GeometryShader = gl::GEOMETRY_SHADER,
+    TessControlShader = gl::TESS_CONTROL_SHADER,
+    TessEvaluationShader = gl::TESS_EVALUATION_SHADER,
+    ComputeShader = gl::COMPUTE_SHADER,
+}
+
+impl std::fmt::Display for ShaderType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ShaderType::VertexShader => "vertex shader",
+            ShaderType::FragmentShader => "fragment shader",
+            ShaderType::GeometryShader => "geometry shader",
+            ShaderType::TessControlShader => "tessellation control shader",
+            ShaderType::TessEvaluationShader => "tessellation evaluation shader",
+            ShaderType::ComputeShader => "compute shader",
+        };
+        f.write_str(name)
+    }
+}
+
+impl ShaderType {
+    // Core version that introduced the stage and the extension offering it
+    // on older contexts. GL_ARB_geometry_shader4 has a different API than
+    // core geometry shaders, so that stage has no fallback.
+    fn requirement(self) -> Option<((i32, i32), Option<&'static str>)> {
+        match self {
+            ShaderType::VertexShader | ShaderType::FragmentShader => None,
+            ShaderType::GeometryShader => Some(((3, 2), None)),
+            ShaderType::TessControlShader | ShaderType::TessEvaluationShader => {
+                Some(((4, 0), Some("GL_ARB_tessellation_shader")))
+            }
+            ShaderType::ComputeShader => Some(((4, 3), Some("GL_ARB_compute_shader"))),
+        }
+    }
+
+    // Errors out when the current context can't compile this stage
+    pub unsafe fn check_supported(self) -> Result<(), String> {
+        let Some((required, extension)) = self.requirement() else {
+            return Ok(());
+        };
+        let version = gl_version();
+        if version >= required || extension.is_some_and(|x| has_gl_extension(x)) {
+            return Ok(());
+        }
+        let alternative = match extension {
+            Some(extension) => format!(" or {}", extension),
+            None => String::new(),
+        };
+        Err(format!(
+            "The {} needs OpenGL {}.{}{} but the context is {}.{}",
+            self, required.0, required.1, alternative, version.0, version.1
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+impl WrapMode {
+    fn to_gl(self) -> u32 {
+        match self {
+            WrapMode::Repeat => gl::REPEAT,
+            WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+            WrapMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+            WrapMode::ClampToBorder => gl::CLAMP_TO_BORDER,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FilterMode {
+    Nearest,
+    Linear,
+}
+
+// Sampling state used when creating a texture. The default matches what the
+// textures always used: repeat wrapping, trilinear filtering and mipmaps.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureOptions {
+    pub wrap_s: WrapMode,
+    pub wrap_t: WrapMode,
+    pub min_filter: FilterMode,
+    pub mag_filter: FilterMode,
+    // only used when mipmaps are generated
+    pub mipmap_filter: FilterMode,
+    pub mipmaps: bool,
+    // only used with WrapMode::ClampToBorder
+    pub border_color: [f32; 4],
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            wrap_s: WrapMode::Repeat,
+            wrap_t: WrapMode::Repeat,
+            min_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            mipmaps: true,
+            border_color: [0.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+// Compared bitwise so options can key the texture cache
+impl PartialEq for TextureOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.wrap_s == other.wrap_s
+            && self.wrap_t == other.wrap_t
+            && self.min_filter == other.min_filter
+            && self.mag_filter == other.mag_filter
+            && self.mipmap_filter == other.mipmap_filter
+            && self.mipmaps == other.mipmaps
+            && self.border_color.map(f32::to_bits) == other.border_color.map(f32::to_bits)
+    }
+}
+
+impl Eq for TextureOptions {}
+
+impl std::hash::Hash for TextureOptions {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.wrap_s.hash(state);
+        self.wrap_t.hash(state);
+        self.min_filter.hash(state);
+        self.mag_filter.hash(state);
+        self.mipmap_filter.hash(state);
+        self.mipmaps.hash(state);
+        self.border_color.map(f32::to_bits).hash(state);
+    }
+}
+
+impl TextureOptions {
+    // Nearest filtering without mipmaps, clamped to the edge. Suits UI sprites
+    // and pixel art.
+    pub fn pixel_art() -> Self {
+        TextureOptions {
+            wrap_s: WrapMode::ClampToEdge,
+            wrap_t: WrapMode::ClampToEdge,
+            min_filter: FilterMode::Nearest,
+            mag_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            mipmaps: false,
+            border_color: [0.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    pub fn wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap_s = wrap;
+        self.wrap_t = wrap;
+        self
+    }
+
+    pub fn wrap_s(mut self, wrap: WrapMode) -> Self {
+        self.wrap_s = wrap;
+        self
+    }
+
+    pub fn wrap_t(mut self, wrap: WrapMode) -> Self {
+        self.wrap_t = wrap;
+        self
+    }
+
+    pub fn filter(mut self, filter: FilterMode) -> Self {
+        self.min_filter = filter;
+        self.mag_filter = filter;
+        self
+    }
+
+    pub fn min_filter(mut self, filter: FilterMode) -> Self {
+        self.min_filter = filter;
+        self
+    }
+
+    pub fn mag_filter(mut self, filter: FilterMode) -> Self {
+        self.mag_filter = filter;
+        self
+    }
+
+    pub fn mipmaps(mut self, mipmaps: bool) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+
+    pub fn mipmap_filter(mut self, filter: FilterMode) -> Self {
+        self.mipmap_filter = filter;
+        self
+    }
+
+    pub fn border_color(mut self, color: [f32; 4]) -> Self {
+        self.border_color = color;
+        self
+    }
+
+    fn gl_min_filter(&self) -> u32 {
+        if !self.mipmaps {
+            return match self.min_filter {
+                FilterMode::Nearest => gl::NEAREST,
+                FilterMode::Linear => gl::LINEAR,
+            };
+        }
+        match (self.min_filter, self.mipmap_filter) {
+            (FilterMode::Nearest, FilterMode::Nearest) => gl::NEAREST_MIPMAP_NEAREST,
+            (FilterMode::Nearest, FilterMode::Linear) => gl::NEAREST_MIPMAP_LINEAR,
+            (FilterMode::Linear, FilterMode::Nearest) => gl::LINEAR_MIPMAP_NEAREST,
+            (FilterMode::Linear, FilterMode::Linear) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    fn gl_mag_filter(&self) -> u32 {
+        match self.mag_filter {
+            FilterMode::Nearest => gl::NEAREST,
+            FilterMode::Linear => gl::LINEAR,
+        }
+    }
+
+    // Sets the sampling parameters on the texture currently bound to `target`
+    unsafe fn apply(&self, target: u32) {
+        gl::TexParameteri(target, gl::TEXTURE_WRAP_S, self.wrap_s.to_gl() as i32);
+        gl::TexParameteri(target, gl::TEXTURE_WRAP_T, self.wrap_t.to_gl() as i32);
+        gl::TexParameteri(target, gl::TEXTURE_MIN_FILTER, self.gl_min_filter() as i32);
+        gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, self.gl_mag_filter() as i32);
+        if self.wrap_s == WrapMode::ClampToBorder || self.wrap_t == WrapMode::ClampToBorder {
+            gl::TexParameterfv(target, gl::TEXTURE_BORDER_COLOR, self.border_color.as_ptr());
+        }
+        if !self.mipmaps {
+            gl::TexParameteri(target, gl::TEXTURE_MAX_LEVEL, 0);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    R8,
+    RG8,
+    RGB8,
+    RGBA8,
+    SRGB8,
+    SRGB8Alpha8,
+    R16F,
+    RGB16F,
+    RGBA16F,
+    RGB32F,
+    RGBA32F,
+    Depth16,
+    Depth24,
+    Depth32F,
+    Depth24Stencil8,
+}
+
+impl PixelFormat {
+    // Picks the format that keeps every channel of the decoded image
+    pub fn from_image(img: &image::DynamicImage) -> Self {
+        use image::DynamicImage::*;
+        match img {
+            ImageLuma8(_) => PixelFormat::R8,
+            ImageLumaA8(_) => PixelFormat::RG8,
+            ImageRgb8(_) => PixelFormat::RGB8,
+            ImageLuma16(_) => PixelFormat::R16F,
+            ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_) => PixelFormat::RGBA16F,
+            // half floats are plenty for radiance maps and halve the size
+            ImageRgb32F(_) => PixelFormat::RGB16F,
+            ImageRgba32F(_) => PixelFormat::RGBA32F,
+            _ => PixelFormat::RGBA8,
+        }
+    }
+
+    pub fn internal_format(self) -> u32 {
+        match self {
+            PixelFormat::R8 => gl::R8,
+            PixelFormat::RG8 => gl::RG8,
+            PixelFormat::RGB8 => gl::RGB8,
+            PixelFormat::RGBA8 => gl::RGBA8,
+            PixelFormat::SRGB8 => gl::SRGB8,
+            PixelFormat::SRGB8Alpha8 => gl::SRGB8_ALPHA8,
+            PixelFormat::R16F => gl::R16F,
+            PixelFormat::RGB16F => gl::RGB16F,
+            PixelFormat::RGBA16F => gl::RGBA16F,
+            PixelFormat::RGB32F => gl::RGB32F,
+            PixelFormat::RGBA32F => gl::RGBA32F,
+            PixelFormat::Depth16 => gl::DEPTH_COMPONENT16,
+            PixelFormat::Depth24 => gl::DEPTH_COMPONENT24,
+            PixelFormat::Depth32F => gl::DEPTH_COMPONENT32F,
+            PixelFormat::Depth24Stencil8 => gl::DEPTH24_STENCIL8,
+        }
+    }
+
+    pub fn upload_format(self) -> u32 {
+        match self {
+            PixelFormat::R8 | PixelFormat::R16F => gl::RED,
+            PixelFormat::RG8 => gl::RG,
+            PixelFormat::RGB8 | PixelFormat::SRGB8 | PixelFormat::RGB16F | PixelFormat::RGB32F => {
+                gl::RGB
+            }
+            PixelFormat::RGBA8
+            | PixelFormat::SRGB8Alpha8
+            | PixelFormat::RGBA16F
+            | PixelFormat::RGBA32F => gl::RGBA,
+            PixelFormat::Depth16 | PixelFormat::Depth24 | PixelFormat::Depth32F => {
+                gl::DEPTH_COMPONENT
+            }
+            PixelFormat::Depth24Stencil8 => gl::DEPTH_STENCIL,
+        }
+    }
+
+    pub fn data_type(self) -> u32 {
+        match self {
+            PixelFormat::R8
+            | PixelFormat::RG8
+            | PixelFormat::RGB8
+            | PixelFormat::RGBA8
+            | PixelFormat::SRGB8
+            | PixelFormat::SRGB8Alpha8 => gl::UNSIGNED_BYTE,
+            PixelFormat::R16F
+            | PixelFormat::RGB16F
+            | PixelFormat::RGBA16F
+            | PixelFormat::RGB32F
+            | PixelFormat::RGBA32F
+            | PixelFormat::Depth16
+            | PixelFormat::Depth24
+            | PixelFormat::Depth32F => gl::FLOAT,
+            PixelFormat::Depth24Stencil8 => gl::UNSIGNED_INT_24_8,
+        }
+    }
+
+    // Size of one texel as stored by the GL
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::R8 => 1,
+            PixelFormat::RG8 | PixelFormat::R16F | PixelFormat::Depth16 => 2,
+            PixelFormat::RGB8 | PixelFormat::SRGB8 | PixelFormat::Depth24 => 3,
+            PixelFormat::RGBA8
+            | PixelFormat::SRGB8Alpha8
+            | PixelFormat::Depth32F
+            | PixelFormat::Depth24Stencil8 => 4,
+            PixelFormat::RGB16F => 6,
+            PixelFormat::RGBA16F => 8,
+            PixelFormat::RGB32F => 12,
+            PixelFormat::RGBA32F => 16,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(
+            self,
+            PixelFormat::R16F
+                | PixelFormat::RGB16F
+                | PixelFormat::RGBA16F
+                | PixelFormat::RGB32F
+                | PixelFormat::RGBA32F
+        )
+    }
+
+    pub fn is_depth(self) -> bool {
+        matches!(
+            self,
+            PixelFormat::Depth16
+                | PixelFormat::Depth24
+                | PixelFormat::Depth32F
+                | PixelFormat::Depth24Stencil8
+        )
+    }
+
+    // Single channel formats are sampled as grayscale and two channel formats
+    // as grayscale plus alpha, so shaders can treat every texture as rgba.
+    fn swizzle(self) -> Option<[i32; 4]> {
+        match self {
+            PixelFormat::R8 | PixelFormat::R16F => {
+                Some([gl::RED, gl::RED, gl::RED, gl::ONE].map(|x| x as i32))
+            }
+            PixelFormat::RG8 => Some([gl::RED, gl::RED, gl::RED, gl::GREEN].map(|x| x as i32)),
+            _ => None,
+        }
+    }
+}
+
+// Converts the image into the pixel layout `format` uploads from
+fn image_to_pixels(img: image::DynamicImage, format: PixelFormat) -> Result<Vec<u8>, String> {
+    let pixels = match format {
+        PixelFormat::R8 => img.into_luma8().into_raw(),
+        PixelFormat::RG8 => img.into_luma_alpha8().into_raw(),
+        PixelFormat::RGB8 | PixelFormat::SRGB8 => img.into_rgb8().into_raw(),
+        PixelFormat::RGBA8 | PixelFormat::SRGB8Alpha8 => img.into_rgba8().into_raw(),
+        PixelFormat::R16F => img
+            .into_luma16()
+            .into_raw()
+            .into_iter()
+            .flat_map(|x| (x as f32 / u16::MAX as f32).to_ne_bytes())
+            .collect(),
+        PixelFormat::RGB16F | PixelFormat::RGB32F => img
+            .into_rgb32f()
+            .into_raw()
+            .into_iter()
+            .flat_map(|x| x.to_ne_bytes())
+            .collect(),
+        PixelFormat::RGBA16F | PixelFormat::RGBA32F => img
+            .into_rgba32f()
+            .into_raw()
+            .into_iter()
+            .flat_map(|x| x.to_ne_bytes())
+            .collect(),
+        _ => {
+            return Err(format!(
+                "Pixel format {:?} can not be uploaded from an image",
+                format
+            ))
+        }
+    };
+    Ok(pixels)
 }
 
 pub struct Texture {
     pub id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    // set when the GL stores the texture block compressed
+    pub compression: Option<CompressedFormat>,
+    // estimated GPU memory of all mip levels
+    pub byte_size: usize,
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.id) }
+    }
+}
+
+// Size of a full mip chain down to 1x1
+fn mip_chain_byte_size(width: u32, height: u32, bytes_per_pixel: usize) -> usize {
+    let (mut width, mut height) = (width as usize, height as usize);
+    let mut size = width * height * bytes_per_pixel;
+    while width > 1 || height > 1 {
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+        size += width * height * bytes_per_pixel;
+    }
+    size
 }
 
 impl Texture {
     pub unsafe fn new(name: &str) -> Result<Self, String> {
-        let path = env::current_exe()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("textures")
-            .join(name);
+        Texture::new_with_options(name, &TextureOptions::default())
+    }
 
-        if !path.exists() {
-            return Err(format!("Texture file not found: {}", path.display()));
-        }
+    pub unsafe fn new_with_options(name: &str, options: &TextureOptions) -> Result<Self, String> {
+        Texture::from_image(load_texture_image(name)?, options)
+    }
 
-        let img = image::io::Reader::open(path)
-            .map_err(|x| x.to_string())?
-            .decode()
-            .map_err(|x| x.to_string())?
-            .flipv();
+    pub unsafe fn new_with_format(
+        name: &str,
+        format: PixelFormat,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        Texture::from_image_with_format(load_texture_image(name)?, format, options)
+    }
 
-        let mut id = 0;
-        gl::GenTextures(1, &mut id);
-        gl::BindTexture(gl::TEXTURE_2D, id);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-        gl::TexParameteri(
-            gl::TEXTURE_2D,
-            gl::TEXTURE_MIN_FILTER,
-            gl::LINEAR_MIPMAP_LINEAR as i32,
+    // Loads a file by path instead of through the asset resolver. The format
+    // is derived from the image when it is None.
+    pub unsafe fn from_file(
+        path: &std::path::Path,
+        format: Option<PixelFormat>,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        let img = load_image_file(path)?;
+        let format = format.unwrap_or_else(|| PixelFormat::from_image(&img));
+        Texture::from_image_with_format(img, format, options)
+    }
+
+    // Decodes an encoded image, e.g. one embedded with `include_bytes!`
+    pub unsafe fn from_bytes(bytes: &[u8], options: &TextureOptions) -> Result<Self, String> {
+        Texture::from_image(decode_image_bytes(bytes)?, options)
+    }
+
+    pub unsafe fn from_image(
+        img: image::DynamicImage,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        let format = PixelFormat::from_image(&img);
+        Texture::from_image_with_format(img, format, options)
+    }
+
+    // The image is expected top row first, it is flipped to match GL's
+    // bottom-up texture coordinates.
+    pub unsafe fn from_image_with_format(
+        img: image::DynamicImage,
+        format: PixelFormat,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        let img = img.flipv();
+        let (width, height) = (img.width(), img.height());
+        let pixels = image_to_pixels(img, format)?;
+        Texture::upload(width, height, format, Some(&pixels), options)
+    }
+
+    pub unsafe fn bind(&self, unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(gl::TEXTURE_2D, self.id);
+    }
+
+    // Binds a mip level to an image unit for `imageLoad`/`imageStore` in
+    // compute shaders, needs GL 4.2. Three channel formats can't be used as
+    // images.
+    pub unsafe fn bind_image(
+        &self,
+        unit: u32,
+        level: u32,
+        access: ImageAccess,
+    ) -> Result<(), String> {
+        if gl_version() < (4, 2) {
+            return Err("Image load/store needs OpenGL 4.2".to_owned());
+        }
+        let valid = matches!(
+            self.format,
+            PixelFormat::R8
+                | PixelFormat::RG8
+                | PixelFormat::RGBA8
+                | PixelFormat::R16F
+                | PixelFormat::RGBA16F
+                | PixelFormat::RGBA32F
         );
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        if !valid || self.compression.is_some() {
+            return Err(format!(
+                "{:?} textures can't be bound as images",
+                self.format
+            ));
+        }
+        gl::BindImageTexture(
+            unit,
+            self.id,
+            level as i32,
+            gl::FALSE,
+            0,
+            access as u32,
+            self.format.internal_format(),
+        );
+        check_gl_error()
+    }
 
-        let rgb8 = img.into_rgb8();
-        gl::TexImage2D(
+    // Reads a mip level back, flipped to top row first like the image it was
+    // loaded from. Single channel float and depth formats come back as
+    // grayscale, compressed textures are decompressed by the driver.
+    pub unsafe fn read_pixels(&self, level: u32) -> Result<image::DynamicImage, String> {
+        gl::BindTexture(gl::TEXTURE_2D, self.id);
+        let mut width = 0;
+        let mut height = 0;
+        gl::GetTexLevelParameteriv(gl::TEXTURE_2D, level as i32, gl::TEXTURE_WIDTH, &mut width);
+        gl::GetTexLevelParameteriv(
             gl::TEXTURE_2D,
-            0,
-            gl::RGB as i32,
-            rgb8.width() as i32,
-            rgb8.height() as i32,
-            0,
-            gl::RGB,
-            gl::UNSIGNED_BYTE,
-            rgb8.as_ptr() as *const c_void,
+            level as i32,
+            gl::TEXTURE_HEIGHT,
+            &mut height,
         );
-        gl::GenerateMipmap(gl::TEXTURE_2D);
+        if width == 0 || height == 0 {
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            return Err(format!("Texture {} has no mip level {}", self.id, level));
+        }
+        let (width, height) = (width as u32, height as u32);
+        let texels = width as usize * height as usize;
+
+        let read = |format: u32, data_type: u32, buffer: *mut c_void| {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::GetTexImage(gl::TEXTURE_2D, level as i32, format, data_type, buffer);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+        };
+        let img = match self.format {
+            PixelFormat::R8 => {
+                let mut buffer = image::GrayImage::new(width, height);
+                read(
+                    gl::RED,
+                    gl::UNSIGNED_BYTE,
+                    buffer.as_mut_ptr() as *mut c_void,
+                );
+                image::DynamicImage::ImageLuma8(buffer)
+            }
+            PixelFormat::RG8 => {
+                let mut buffer = image::GrayAlphaImage::new(width, height);
+                read(
+                    gl::RG,
+                    gl::UNSIGNED_BYTE,
+                    buffer.as_mut_ptr() as *mut c_void,
+                );
+                image::DynamicImage::ImageLumaA8(buffer)
+            }
+            PixelFormat::RGB8 | PixelFormat::SRGB8 => {
+                let mut buffer = image::RgbImage::new(width, height);
+                read(
+                    gl::RGB,
+                    gl::UNSIGNED_BYTE,
+                    buffer.as_mut_ptr() as *mut c_void,
+                );
+                image::DynamicImage::ImageRgb8(buffer)
+            }
+            PixelFormat::RGBA8 | PixelFormat::SRGB8Alpha8 => {
+                let mut buffer = image::RgbaImage::new(width, height);
+                read(
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    buffer.as_mut_ptr() as *mut c_void,
+                );
+                image::DynamicImage::ImageRgba8(buffer)
+            }
+            PixelFormat::RGB16F | PixelFormat::RGB32F => {
+                let mut buffer = image::Rgb32FImage::new(width, height);
+                read(gl::RGB, gl::FLOAT, buffer.as_mut_ptr() as *mut c_void);
+                image::DynamicImage::ImageRgb32F(buffer)
+            }
+            PixelFormat::RGBA16F | PixelFormat::RGBA32F => {
+                let mut buffer = image::Rgba32FImage::new(width, height);
+                read(gl::RGBA, gl::FLOAT, buffer.as_mut_ptr() as *mut c_void);
+                image::DynamicImage::ImageRgba32F(buffer)
+            }
+            PixelFormat::R16F => {
+                let mut values = vec![0.0f32; texels];
+                read(gl::RED, gl::FLOAT, values.as_mut_ptr() as *mut c_void);
+                let rgb = values.into_iter().flat_map(|x| [x, x, x]).collect();
+                image::DynamicImage::ImageRgb32F(
+                    image::Rgb32FImage::from_raw(width, height, rgb).unwrap(),
+                )
+            }
+            PixelFormat::Depth16
+            | PixelFormat::Depth24
+            | PixelFormat::Depth32F
+            | PixelFormat::Depth24Stencil8 => {
+                let mut values = vec![0.0f32; texels];
+                read(
+                    gl::DEPTH_COMPONENT,
+                    gl::FLOAT,
+                    values.as_mut_ptr() as *mut c_void,
+                );
+                let depth = values
+                    .into_iter()
+                    .map(|x| (x.clamp(0.0, 1.0) * u16::MAX as f32) as u16)
+                    .collect();
+                image::DynamicImage::ImageLuma16(
+                    image::ImageBuffer::from_raw(width, height, depth).unwrap(),
+                )
+            }
+        };
         gl::BindTexture(gl::TEXTURE_2D, 0);
-        Ok(Texture { id })
+        check_gl_error()?;
+        Ok(img.flipv())
     }
 
-    pub unsafe fn new_rgba(name: &str) -> Result<Self, String> {
-        let path = env::current_exe()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("textures")
-            .join(name);
+    // Allocates storage without any pixel data, e.g. for depth textures
+    pub unsafe fn empty(
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        Texture::upload(width, height, format, None, options)
+    }
 
-        if !path.exists() {
-            return Err(format!("Texture file not found: {}", path.display()));
+    unsafe fn upload(
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        pixels: Option<&[u8]>,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        let mut id = 0;
+        gl::GenTextures(1, &mut id);
+        gl::BindTexture(gl::TEXTURE_2D, id);
+        options.apply(gl::TEXTURE_2D);
+        if let Some(swizzle) = format.swizzle() {
+            gl::TexParameteriv(gl::TEXTURE_2D, gl::TEXTURE_SWIZZLE_RGBA, swizzle.as_ptr());
         }
 
-        let img = image::io::Reader::open(path)
-            .map_err(|x| x.to_string())?
-            .decode()
-            .map_err(|x| x.to_string())?
-            .flipv();
+        tex_image_2d(gl::TEXTURE_2D, 0, width, height, format, pixels);
+        let byte_size = if options.mipmaps && pixels.is_some() {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            mip_chain_byte_size(width, height, format.bytes_per_pixel())
+        } else {
+            width as usize * height as usize * format.bytes_per_pixel()
+        };
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        check_gl_error()?;
+        Ok(Texture {
+            id,
+            width,
+            height,
+            format,
+            compression: None,
+            byte_size,
+        })
+    }
+
+    // Loads a KTX2 or DDS file
+    pub unsafe fn new_compressed(name: &str, options: &TextureOptions) -> Result<Self, String> {
+        let path = AssetResolver::new().texture(name)?;
+        let bytes = std::fs::read(path).map_err(|x| x.to_string())?;
+        Texture::from_compressed(&CompressedImage::from_bytes(&bytes)?, options)
+    }
+
+    // Uploads the stored mip chain as is when the driver supports the format,
+    // otherwise every level is decoded on the CPU first. Blocks are not
+    // flipped like `Texture::new` does, so export them with a bottom left
+    // origin (e.g. `toktx --lower_left_maps_to_s0t0`).
+    pub unsafe fn from_compressed(
+        image: &CompressedImage,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        let levels = if options.mipmaps {
+            image.levels.len()
+        } else {
+            1
+        };
+        let supported = image.format.is_supported();
+        let format = image.format.decoded_format();
 
         let mut id = 0;
         gl::GenTextures(1, &mut id);
         gl::BindTexture(gl::TEXTURE_2D, id);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-        gl::TexParameteri(
-            gl::TEXTURE_2D,
-            gl::TEXTURE_MIN_FILTER,
-            gl::LINEAR_MIPMAP_LINEAR as i32,
-        );
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-
-        let rgba8 = img.into_rgba8();
-        gl::TexImage2D(
-            gl::TEXTURE_2D,
-            0,
-            gl::RGB as i32,
-            rgba8.width() as i32,
-            rgba8.height() as i32,
-            0,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            rgba8.as_ptr() as *const c_void,
-        );
-        gl::GenerateMipmap(gl::TEXTURE_2D);
+        options.apply(gl::TEXTURE_2D);
+        // mipmaps can't be generated from compressed data, use the stored ones
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, levels as i32 - 1);
+        let mut byte_size = 0;
+        for level in 0..levels {
+            let (width, height) = image.level_size(level);
+            if supported {
+                let data = &image.levels[level];
+                byte_size += data.len();
+                gl::CompressedTexImage2D(
+                    gl::TEXTURE_2D,
+                    level as i32,
+                    image.format.internal_format(),
+                    width as i32,
+                    height as i32,
+                    0,
+                    data.len() as i32,
+                    data.as_ptr() as *const c_void,
+                );
+            } else {
+                let pixels = image.decode_level(level);
+                byte_size += pixels.len();
+                tex_image_2d(
+                    gl::TEXTURE_2D,
+                    level as i32,
+                    width,
+                    height,
+                    format,
+                    Some(&pixels),
+                );
+            }
+        }
         gl::BindTexture(gl::TEXTURE_2D, 0);
-        Ok(Texture { id })
+        check_gl_error()?;
+        Ok(Texture {
+            id,
+            width: image.width,
+            height: image.height,
+            format,
+            compression: supported.then_some(image.format),
+            byte_size,
+        })
     }
 }
 
-pub struct Shader {
-    pub id: u32,
+unsafe fn tex_image_2d(
+    target: u32,
+    level: i32,
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    pixels: Option<&[u8]>,
+) {
+    // rows of R8 and RGB8 images are not padded to 4 bytes
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    gl::TexImage2D(
+        target,
+        level,
+        format.internal_format() as i32,
+        width as i32,
+        height as i32,
+        0,
+        format.upload_format(),
+        format.data_type(),
+        pixels.map_or(std::ptr::null(), |x| x.as_ptr() as *const c_void),
+    );
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
 }
 
-impl Shader {
-    pub unsafe fn from_str(source: &str, shader_type: ShaderType) -> Result<Self, String> {
-        let id = unsafe { gl::CreateShader(shader_type as u32) };
-        let c_shader_source = std::ffi::CString::new(source).map_err(|e| e.to_string())?;
+// S3TC enums are an extension and not part of the generated bindings
+const COMPRESSED_RGBA_S3TC_DXT1_EXT: u32 = 0x83F1;
+const COMPRESSED_RGBA_S3TC_DXT5_EXT: u32 = 0x83F3;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT: u32 = 0x8C4D;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT: u32 = 0x8C4F;
 
-        gl::ShaderSource(
-            id,
-            1,
-            &(c_shader_source.as_ptr() as *const i8) as *const *const i8,
-            std::ptr::null(),
-        );
-        gl::CompileShader(id);
-        check_shader_compile_errors(id)?;
-        Ok(Shader { id })
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompressedFormat {
+    Bc1,
+    Bc1Srgb,
+    Bc3,
+    Bc3Srgb,
+    Bc4,
+    Bc5,
+    Bc7,
+    Bc7Srgb,
 }
 
-impl Drop for Shader {
-    fn drop(&mut self) {
-        unsafe { gl::DeleteShader(self.id) }
+impl CompressedFormat {
+    pub fn internal_format(self) -> u32 {
+        match self {
+            CompressedFormat::Bc1 => COMPRESSED_RGBA_S3TC_DXT1_EXT,
+            CompressedFormat::Bc1Srgb => COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT,
+            CompressedFormat::Bc3 => COMPRESSED_RGBA_S3TC_DXT5_EXT,
+            CompressedFormat::Bc3Srgb => COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT,
+            CompressedFormat::Bc4 => gl::COMPRESSED_RED_RGTC1,
+            CompressedFormat::Bc5 => gl::COMPRESSED_RG_RGTC2,
+            CompressedFormat::Bc7 => gl::COMPRESSED_RGBA_BPTC_UNORM,
+            CompressedFormat::Bc7Srgb => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+        }
+    }
+
+    // Format the blocks decode to, used when the driver can't sample them
+    pub fn decoded_format(self) -> PixelFormat {
+        match self {
+            CompressedFormat::Bc1 | CompressedFormat::Bc3 | CompressedFormat::Bc7 => {
+                PixelFormat::RGBA8
+            }
+            CompressedFormat::Bc1Srgb | CompressedFormat::Bc3Srgb | CompressedFormat::Bc7Srgb => {
+                PixelFormat::SRGB8Alpha8
+            }
+            CompressedFormat::Bc4 => PixelFormat::R8,
+            CompressedFormat::Bc5 => PixelFormat::RG8,
+        }
+    }
+
+    // Bytes per 4x4 block
+    pub fn block_size(self) -> usize {
+        match self {
+            CompressedFormat::Bc1 | CompressedFormat::Bc1Srgb | CompressedFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    // Saturates instead of overflowing for sizes read from broken files
+    pub fn level_byte_size(self, width: u32, height: u32) -> usize {
+        let blocks_x = (width as usize).div_ceil(4);
+        let blocks_y = (height as usize).div_ceil(4);
+        blocks_x
+            .saturating_mul(blocks_y)
+            .saturating_mul(self.block_size())
+    }
+
+    pub unsafe fn is_supported(self) -> bool {
+        match self {
+            // RGTC is core since 3.0
+            CompressedFormat::Bc4 | CompressedFormat::Bc5 => true,
+            CompressedFormat::Bc1 | CompressedFormat::Bc3 => {
+                has_gl_extension("GL_EXT_texture_compression_s3tc")
+            }
+            CompressedFormat::Bc1Srgb | CompressedFormat::Bc3Srgb => {
+                has_gl_extension("GL_EXT_texture_compression_s3tc")
+                    && (has_gl_extension("GL_EXT_texture_sRGB")
+                        || has_gl_extension("GL_EXT_texture_compression_s3tc_srgb"))
+            }
+            CompressedFormat::Bc7 | CompressedFormat::Bc7Srgb => {
+                gl_version() >= (4, 2) || has_gl_extension("GL_ARB_texture_compression_bptc")
+            }
+        }
     }
 }
 
-pub struct ShaderProgram {
-    pub id: u32,
+// Returns the (major, minor) version of the current context
+pub unsafe fn gl_version() -> (i32, i32) {
+    let mut major = 0;
+    let mut minor = 0;
+    gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+    gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+    (major, minor)
 }
 
-impl ShaderProgram {
-    pub unsafe fn new() -> Self {
-        let id = gl::CreateProgram();
-        ShaderProgram { id }
-    }
+pub unsafe fn has_gl_extension(name: &str) -> bool {
+    let mut count = 0;
+    gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+    (0..count as u32).any(|idx| {
+        let ext = gl::GetStringi(gl::EXTENSIONS, idx);
+        !ext.is_null() && std::ffi::CStr::from_ptr(ext as *const _).to_bytes() == name.as_bytes()
+    })
+}
 
-    pub unsafe fn attach_shader(&self, shader: Shader) -> () {
-        gl::AttachShader(self.id, shader.id);
+// Block compressed image with its pre-built mip chain, level 0 first
+pub struct CompressedImage {
+    pub format: CompressedFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+        .ok_or_else(|| "Unexpected end of file".to_owned())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    bytes
+        .get(offset..offset + 8)
+        .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+        .ok_or_else(|| "Unexpected end of file".to_owned())
+}
+
+// Errors out when a header claims more mip levels than the full chain of a
+// `width` x `height` image has, before anything is allocated for them
+fn check_level_count(
+    container: &str,
+    level_count: u32,
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    let max_levels = (32 - width.max(height).leading_zeros()).max(1);
+    if level_count > max_levels {
+        return Err(format!(
+            "{} header claims {} levels but a {}x{} image has at most {}",
+            container, level_count, width, height, max_levels
+        ));
     }
+    Ok(())
+}
 
-    pub unsafe fn link_program(&self) -> Result<(), String> {
-        gl::LinkProgram(self.id);
-        check_shader_link_errors(self.id)?;
-        Ok(())
+impl CompressedImage {
+    // Detects the container from its magic bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            CompressedImage::from_ktx2(bytes)
+        } else if bytes.starts_with(b"DDS ") {
+            CompressedImage::from_dds(bytes)
+        } else {
+            Err("Unknown compressed texture container, expected KTX2 or DDS".to_owned())
+        }
     }
 
-    pub unsafe fn use_program(&self) {
-        gl::UseProgram(self.id);
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.starts_with(&KTX2_IDENTIFIER) {
+            return Err("Not a KTX2 file".to_owned());
+        }
+        let vk_format = read_u32(bytes, 12)?;
+        let width = read_u32(bytes, 20)?;
+        let height = read_u32(bytes, 24)?;
+        let depth = read_u32(bytes, 28)?;
+        let layers = read_u32(bytes, 32)?;
+        let faces = read_u32(bytes, 36)?;
+        let level_count = read_u32(bytes, 40)?.max(1);
+        let supercompression = read_u32(bytes, 44)?;
+
+        // VkFormat values of the BCn block formats
+        let format = match vk_format {
+            131 | 133 => CompressedFormat::Bc1,
+            132 | 134 => CompressedFormat::Bc1Srgb,
+            137 => CompressedFormat::Bc3,
+            138 => CompressedFormat::Bc3Srgb,
+            139 => CompressedFormat::Bc4,
+            141 => CompressedFormat::Bc5,
+            145 => CompressedFormat::Bc7,
+            146 => CompressedFormat::Bc7Srgb,
+            _ => return Err(format!("Unsupported KTX2 VkFormat {}", vk_format)),
+        };
+        if supercompression != 0 {
+            return Err(format!(
+                "KTX2 supercompression scheme {} is not supported",
+                supercompression
+            ));
+        }
+        if depth > 1 || layers > 1 || faces != 1 {
+            return Err("Only single 2D KTX2 textures are supported".to_owned());
+        }
+
+        check_level_count("KTX2", level_count, width, height)?;
+
+        let mut levels = Vec::with_capacity(level_count as usize);
+        for level in 0..level_count {
+            // level index entries are byteOffset, byteLength, uncompressedByteLength
+            let entry = 80 + level as usize * 24;
+            let offset = read_u64(bytes, entry)?;
+            let length = read_u64(bytes, entry + 8)?;
+            let data = offset
+                .checked_add(length)
+                .filter(|end| *end <= bytes.len() as u64)
+                .map(|end| &bytes[offset as usize..end as usize])
+                .ok_or_else(|| format!("KTX2 level {} is out of bounds", level))?;
+            levels.push(data.to_vec());
+        }
+        CompressedImage::new(format, width, height, levels)
     }
 
-    unsafe fn get_uniform_location(&self, name: &str) -> Result<i32, String> {
-        let cstr = std::ffi::CString::new(name).unwrap();
-        let location = gl::GetUniformLocation(self.id, cstr.as_ptr() as *const i8);
-        if location == -1 {
-            Err(format!("Failed to find location of uniform {}", name))
+    pub fn from_dds(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.starts_with(b"DDS ") {
+            return Err("Not a DDS file".to_owned());
+        }
+        const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+        const DDSCAPS2_CUBEMAP: u32 = 0x200;
+        const DDSCAPS2_VOLUME: u32 = 0x200000;
+        const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+        let flags = read_u32(bytes, 8)?;
+        let height = read_u32(bytes, 12)?;
+        let width = read_u32(bytes, 16)?;
+        let mip_count = if flags & DDSD_MIPMAPCOUNT != 0 {
+            read_u32(bytes, 28)?.max(1)
         } else {
-            Ok(location)
+            1
+        };
+        let four_cc = bytes.get(84..88).ok_or("Unexpected end of file")?;
+        // only the first face or slice would be loaded
+        if read_u32(bytes, 112)? & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+            return Err("DDS cubemaps and volume textures are not supported".to_owned());
+        }
+
+        let (format, mut offset): (CompressedFormat, usize) = match four_cc {
+            b"DXT1" => (CompressedFormat::Bc1, 128),
+            b"DXT5" => (CompressedFormat::Bc3, 128),
+            b"ATI1" | b"BC4U" => (CompressedFormat::Bc4, 128),
+            b"ATI2" | b"BC5U" => (CompressedFormat::Bc5, 128),
+            b"DX10" => {
+                let format = match read_u32(bytes, 128)? {
+                    71 => CompressedFormat::Bc1,
+                    72 => CompressedFormat::Bc1Srgb,
+                    77 => CompressedFormat::Bc3,
+                    78 => CompressedFormat::Bc3Srgb,
+                    80 => CompressedFormat::Bc4,
+                    83 => CompressedFormat::Bc5,
+                    98 => CompressedFormat::Bc7,
+                    99 => CompressedFormat::Bc7Srgb,
+                    dxgi_format => {
+                        return Err(format!("Unsupported DDS DXGI format {}", dxgi_format))
+                    }
+                };
+                if read_u32(bytes, 136)? & DDS_RESOURCE_MISC_TEXTURECUBE != 0 {
+                    return Err("DDS cubemaps and volume textures are not supported".to_owned());
+                }
+                if read_u32(bytes, 140)? > 1 {
+                    return Err("DDS texture arrays are not supported".to_owned());
+                }
+                (format, 148)
+            }
+            _ => {
+                return Err(format!(
+                    "Unsupported DDS FourCC {}",
+                    String::from_utf8_lossy(four_cc)
+                ))
+            }
+        };
+
+        check_level_count("DDS", mip_count, width, height)?;
+
+        let mut levels = Vec::with_capacity(mip_count as usize);
+        for level in 0..mip_count {
+            let size = format.level_byte_size((width >> level).max(1), (height >> level).max(1));
+            let data = offset
+                .checked_add(size)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| format!("DDS level {} is out of bounds", level))?;
+            levels.push(data.to_vec());
+            offset += size;
         }
+        CompressedImage::new(format, width, height, levels)
     }
 
-    pub unsafe fn set_uniform_i32(&self, name: &str, value: i32) -> Result<(), String> {
-        let location = self.get_uniform_location(name)?;
-        self.use_program();
-        gl::Uniform1i(location, value);
-        check_gl_error()?;
-        Ok(())
+    pub fn new(
+        format: CompressedFormat,
+        width: u32,
+        height: u32,
+        levels: Vec<Vec<u8>>,
+    ) -> Result<Self, String> {
+        let image = CompressedImage {
+            format,
+            width,
+            height,
+            levels,
+        };
+        for (level, data) in image.levels.iter().enumerate() {
+            let (width, height) = image.level_size(level);
+            let expected = format.level_byte_size(width, height);
+            if data.len() != expected {
+                return Err(format!(
+                    "Level {} of the {:?} image has {} bytes, expected {}",
+                    level,
+                    format,
+                    data.len(),
+                    expected
+                ));
+            }
+        }
+        Ok(image)
     }
 
-    pub unsafe fn set_uniform_f32(&self, name: &str, value: f32) -> Result<(), String> {
-        let location = self.get_uniform_location(name)?;
-        self.use_program();
-        gl::Uniform1f(location, value);
-        check_gl_error()?;
-        Ok(())
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        // levels past the end of the chain stay 1x1
+        let shift = u32::try_from(level).unwrap_or(u32::MAX);
+        let size = |x: u32| x.checked_shr(shift).unwrap_or(0).max(1);
+        (size(self.width), size(self.height))
     }
 
-    pub unsafe fn set_uniform_mat4(&self, name: &str, value: &glam::Mat4) -> Result<(), String> {
-        let location = self.get_uniform_location(name)?;
-        self.use_program();
-        gl::UniformMatrix4fv(location, 1, gl::FALSE, &value.to_cols_array()[0]);
-        check_gl_error()?;
-        Ok(())
+    // Decodes a level on the CPU into pixels of `format.decoded_format()`
+    pub fn decode_level(&self, level: usize) -> Vec<u8> {
+        let (width, height) = self.level_size(level);
+        let channels = self.format.decoded_format().bytes_per_pixel();
+        let blocks_x = (width as usize).div_ceil(4);
+        let mut pixels = vec![0; width as usize * height as usize * channels];
+
+        let block_size = self.format.block_size();
+        for (idx, block) in self.levels[level].chunks_exact(block_size).enumerate() {
+            let texels = match self.format {
+                CompressedFormat::Bc1 | CompressedFormat::Bc1Srgb => decode_bc1_block(block, false),
+                CompressedFormat::Bc3 | CompressedFormat::Bc3Srgb => {
+                    let mut texels = decode_bc1_block(&block[8..], true);
+                    let alpha = decode_bc4_block(&block[..8]);
+                    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+                        texel[3] = alpha;
+                    }
+                    texels
+                }
+                CompressedFormat::Bc4 => decode_bc4_block(block).map(|x| [x, 0, 0, 255]),
+                CompressedFormat::Bc5 => {
+                    let red = decode_bc4_block(&block[..8]);
+                    let green = decode_bc4_block(&block[8..]);
+                    std::array::from_fn(|x| [red[x], green[x], 0, 255])
+                }
+                CompressedFormat::Bc7 | CompressedFormat::Bc7Srgb => {
+                    decode_bc7_block(block.try_into().unwrap())
+                }
+            };
+
+            // copy the 4x4 texels, clipping blocks that hang over the edge
+            let (block_x, block_y) = (idx % blocks_x * 4, idx / blocks_x * 4);
+            for (texel_idx, texel) in texels.iter().enumerate() {
+                let x = block_x + texel_idx % 4;
+                let y = block_y + texel_idx / 4;
+                if x < width as usize && y < height as usize {
+                    let offset = (y * width as usize + x) * channels;
+                    pixels[offset..offset + channels].copy_from_slice(&texel[..channels]);
+                }
+            }
+        }
+        pixels
+    }
+}
+
+fn unpack_rgb565(color: u16) -> [u8; 4] {
+    let r = ((color >> 11) & 31) as u8;
+    let g = ((color >> 5) & 63) as u8;
+    let b = (color & 31) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+        255,
+    ]
+}
+
+// BC3 color blocks always use the four color mode
+fn decode_bc1_block(block: &[u8], four_color: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let (e0, e1) = (unpack_rgb565(c0), unpack_rgb565(c1));
+
+    let mix = |a: u32, b: u32| -> [u8; 4] {
+        let total = a + b;
+        std::array::from_fn(|x| ((e0[x] as u32 * a + e1[x] as u32 * b) / total) as u8)
+    };
+    let palette = if c0 > c1 || four_color {
+        [e0, e1, mix(2, 1), mix(1, 2)]
+    } else {
+        [e0, e1, mix(1, 1), [0, 0, 0, 0]]
+    };
+    std::array::from_fn(|x| palette[(indices >> (2 * x) & 3) as usize])
+}
+
+fn decode_bc4_block(block: &[u8]) -> [u8; 16] {
+    let (r0, r1) = (block[0] as u32, block[1] as u32);
+    let mut indices = [0; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+
+    let mut palette = [r0, r1, 0, 0, 0, 0, 0, 255];
+    if r0 > r1 {
+        for (idx, value) in palette.iter_mut().enumerate().skip(2) {
+            *value = ((8 - idx as u32) * r0 + (idx as u32 - 1) * r1 + 3) / 7;
+        }
+    } else {
+        for (idx, value) in palette.iter_mut().enumerate().take(6).skip(2) {
+            *value = ((6 - idx as u32) * r0 + (idx as u32 - 1) * r1 + 2) / 5;
+        }
+    }
+    std::array::from_fn(|x| palette[(indices >> (3 * x) & 7) as usize] as u8)
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index_bits2: u32,
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+];
+
+// Two subset partitions, bit n is the subset of texel n
+#[rustfmt::skip]
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+// Three subset partitions, bits 2n..2n+1 are the subset of texel n
+#[rustfmt::skip]
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050,
+    0x5555A0A0, 0x5A5A5050, 0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090,
+    0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250, 0xA5945040, 0x0A425054,
+    0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414,
+    0x50A4A450, 0x6A5A0200, 0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424,
+    0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50, 0x500AA550, 0xAAAA4444,
+    0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580,
+    0xAA141414, 0x96960000, 0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000,
+    0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+// Texel whose index is stored with one bit less, for subset 1 of the two
+// subset partitions and subsets 1 and 2 of the three subset partitions
+#[rustfmt::skip]
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15,  2,  8,  2,  2,  8,  8, 15,  2,  8,  2,  2,  8,  8,  2,  2,
+    15, 15,  6,  8,  2,  8, 15, 15,  2,  8,  2,  2,  2, 15, 15,  6,
+     6,  2,  6,  8, 15, 15,  2,  2, 15, 15, 15, 15, 15,  2,  2, 15,
+];
+
+#[rustfmt::skip]
+const BC7_ANCHORS_3A: [u8; 64] = [
+     3,  3, 15, 15,  8,  3, 15, 15,  8,  8,  6,  6,  6,  5,  3,  3,
+     3,  3,  8, 15,  3,  3,  6, 10,  5,  8,  8,  6,  8,  5, 15, 15,
+     8, 15,  3,  5,  6, 10,  8, 15, 15,  3, 15,  5, 15, 15, 15, 15,
+     3, 15,  5,  5,  5,  8,  5, 10,  5, 10,  8, 13, 15, 12,  3,  3,
+];
+
+#[rustfmt::skip]
+const BC7_ANCHORS_3B: [u8; 64] = [
+    15,  8,  8,  3, 15, 15,  3,  8, 15, 15, 15, 15, 15, 15, 15,  8,
+    15,  8, 15,  3, 15,  8, 15,  8,  3, 15,  6, 10, 15, 15, 10,  8,
+    15,  3, 15, 10, 10,  8,  9, 10,  6, 15,  8, 15,  3,  6,  6,  8,
+    15,  3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,  3, 15, 15,  8,
+];
+
+fn bc7_weights(bits: u32) -> &'static [u32] {
+    match bits {
+        2 => &[0, 21, 43, 64],
+        3 => &[0, 9, 18, 27, 37, 46, 55, 64],
+        _ => &[0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64],
+    }
+}
+
+fn decode_bc7_block(block: [u8; 16]) -> [[u8; 4]; 16] {
+    let bits = u128::from_le_bytes(block);
+    let mut pos = 0;
+    let mut read = |count: u32| -> u32 {
+        let value = (bits >> pos) as u32 & ((1u64 << count) - 1) as u32;
+        pos += count;
+        value
+    };
+
+    // the mode is the number of zero bits before the first set bit
+    let mode_idx = block[0].trailing_zeros() as usize;
+    if mode_idx >= BC7_MODES.len() {
+        return [[0; 4]; 16];
+    }
+    let mode = &BC7_MODES[mode_idx];
+    read(mode_idx as u32 + 1);
+    let partition = read(mode.partition_bits) as usize;
+    let rotation = read(mode.rotation_bits);
+    let index_selection = read(mode.index_selection_bits);
+
+    // endpoints are stored channel by channel, two per subset
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = read(mode.color_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = if mode.alpha_bits > 0 {
+            read(mode.alpha_bits)
+        } else {
+            255
+        };
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_pbits || mode.shared_pbits {
+        let mut pbits = [0; 6];
+        if mode.endpoint_pbits {
+            for pbit in pbits.iter_mut().take(endpoint_count) {
+                *pbit = read(1);
+            }
+        } else {
+            for subset in 0..mode.subsets {
+                let pbit = read(1);
+                pbits[subset * 2] = pbit;
+                pbits[subset * 2 + 1] = pbit;
+            }
+        }
+        for (endpoint, pbit) in endpoints.iter_mut().zip(pbits).take(endpoint_count) {
+            for value in endpoint.iter_mut().take(3) {
+                *value = *value << 1 | pbit;
+            }
+            if mode.alpha_bits > 0 {
+                endpoint[3] = endpoint[3] << 1 | pbit;
+            }
+        }
+        color_bits += 1;
+        if mode.alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+
+    // expand to 8 bits by replicating the high bits into the low bits
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for value in endpoint.iter_mut().take(3) {
+            let shifted = *value << (8 - color_bits);
+            *value = shifted | shifted >> color_bits;
+        }
+        if mode.alpha_bits > 0 {
+            let value = endpoint[3] << (8 - alpha_bits);
+            endpoint[3] = value | value >> alpha_bits;
+        }
+    }
+
+    let subset_of = |texel: usize| -> usize {
+        match mode.subsets {
+            1 => 0,
+            2 => (BC7_PARTITIONS_2[partition] >> texel & 1) as usize,
+            _ => (BC7_PARTITIONS_3[partition] >> (2 * texel) & 3) as usize,
+        }
+    };
+    let is_anchor = |texel: usize| -> bool {
+        texel == 0
+            || match mode.subsets {
+                2 => texel == BC7_ANCHORS_2[partition] as usize,
+                3 => {
+                    texel == BC7_ANCHORS_3A[partition] as usize
+                        || texel == BC7_ANCHORS_3B[partition] as usize
+                }
+                _ => false,
+            }
+    };
+
+    let mut indices = [0; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        *index = read(mode.index_bits - is_anchor(texel) as u32);
+    }
+    let mut indices2 = [0; 16];
+    if mode.index_bits2 > 0 {
+        for (texel, index) in indices2.iter_mut().enumerate() {
+            *index = read(mode.index_bits2 - (texel == 0) as u32);
+        }
+    }
+
+    std::array::from_fn(|texel| {
+        let subset = subset_of(texel);
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+        let (color_weight, alpha_weight) = if mode.index_bits2 == 0 {
+            let weight = bc7_weights(mode.index_bits)[indices[texel] as usize];
+            (weight, weight)
+        } else if index_selection == 0 {
+            (
+                bc7_weights(mode.index_bits)[indices[texel] as usize],
+                bc7_weights(mode.index_bits2)[indices2[texel] as usize],
+            )
+        } else {
+            (
+                bc7_weights(mode.index_bits2)[indices2[texel] as usize],
+                bc7_weights(mode.index_bits)[indices[texel] as usize],
+            )
+        };
+        let interpolate = |channel: usize, weight: u32| {
+            (((64 - weight) * e0[channel] + weight * e1[channel] + 32) >> 6) as u8
+        };
+        let mut texel = [
+            interpolate(0, color_weight),
+            interpolate(1, color_weight),
+            interpolate(2, color_weight),
+            interpolate(3, alpha_weight),
+        ];
+        if rotation > 0 {
+            texel.swap(3, rotation as usize - 1);
+        }
+        texel
+    })
+}
+
+// Faces are in GL order: +X, -X, +Y, -Y, +Z, -Z. Unlike 2D textures cubemap
+// faces are not flipped, their origin is the top left corner. Creating one
+// enables GL_TEXTURE_CUBE_MAP_SEAMLESS so filtering blends across face edges
+// instead of showing seams. That is context state, it applies to every
+// cubemap sampled afterwards.
+pub struct Cubemap {
+    pub id: u32,
+    pub size: u32,
+    pub format: PixelFormat,
+}
+
+impl Cubemap {
+    pub unsafe fn new(names: [&str; 6], options: &TextureOptions) -> Result<Self, String> {
+        let mut faces = Vec::with_capacity(6);
+        for name in names {
+            faces.push(load_texture_image(name)?);
+        }
+        let faces: [image::DynamicImage; 6] = faces.try_into().unwrap();
+        Cubemap::from_faces(faces, options)
+    }
+
+    pub unsafe fn from_faces(
+        faces: [image::DynamicImage; 6],
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        let size = faces[0].width();
+        let format = PixelFormat::from_image(&faces[0]);
+        for face in &faces {
+            if face.width() != size || face.height() != size {
+                return Err(format!(
+                    "Cubemap faces must be square and the same size, expected {size}x{size} got {}x{}",
+                    face.width(),
+                    face.height()
+                ));
+            }
+        }
+
+        gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+        let mut id = 0;
+        gl::GenTextures(1, &mut id);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, id);
+        options.apply(gl::TEXTURE_CUBE_MAP);
+        // sampling across face edges needs clamping on every axis
+        for wrap in [gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, wrap, gl::CLAMP_TO_EDGE as i32);
+        }
+        for (idx, face) in faces.into_iter().enumerate() {
+            let pixels = image_to_pixels(face, format)?;
+            tex_image_2d(
+                gl::TEXTURE_CUBE_MAP_POSITIVE_X + idx as u32,
+                0,
+                size,
+                size,
+                format,
+                Some(&pixels),
+            );
+        }
+        if options.mipmaps {
+            gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+        }
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        check_gl_error()?;
+        Ok(Cubemap { id, size, format })
+    }
+
+    // Loads a horizontal (4x3) or vertical (3x4) cross:
+    //         +Y                    +Y
+    //     -X  +Z  +X  -Z        -X  +Z  +X
+    //         -Y                    -Y
+    //                               -Z (upside down)
+    pub unsafe fn from_cross(
+        img: image::DynamicImage,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        let (width, height) = (img.width(), img.height());
+        let faces = if width / 4 == height / 3 && width % 4 == 0 && height % 3 == 0 {
+            let size = width / 4;
+            let face = |x: u32, y: u32| img.crop_imm(x * size, y * size, size, size);
+            [
+                face(2, 1),
+                face(0, 1),
+                face(1, 0),
+                face(1, 2),
+                face(1, 1),
+                face(3, 1),
+            ]
+        } else if width / 3 == height / 4 && width % 3 == 0 && height % 4 == 0 {
+            let size = width / 3;
+            let face = |x: u32, y: u32| img.crop_imm(x * size, y * size, size, size);
+            [
+                face(2, 1),
+                face(0, 1),
+                face(1, 0),
+                face(1, 2),
+                face(1, 1),
+                face(1, 3).rotate180(),
+            ]
+        } else {
+            return Err(format!(
+                "Cubemap cross image must be 4:3 or 3:4, got {width}x{height}"
+            ));
+        };
+        Cubemap::from_faces(faces, options)
+    }
+
+    // Resamples an equirectangular (longitude/latitude) panorama into six
+    // faces of `size` pixels
+    pub unsafe fn from_equirect(
+        img: image::DynamicImage,
+        size: u32,
+        options: &TextureOptions,
+    ) -> Result<Self, String> {
+        let hdr = PixelFormat::from_image(&img).is_float();
+        let src = img.into_rgba32f();
+        let faces = [0, 1, 2, 3, 4, 5].map(|face| {
+            let face = image::Rgba32FImage::from_fn(size, size, |x, y| {
+                let s = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+                let t = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+                sample_equirect(&src, cube_face_direction(face, s, t))
+            });
+            if hdr {
+                image::DynamicImage::ImageRgba32F(face)
+            } else {
+                image::DynamicImage::ImageRgba8(
+                    image::DynamicImage::ImageRgba32F(face).into_rgba8(),
+                )
+            }
+        });
+        Cubemap::from_faces(faces, options)
+    }
+
+    pub unsafe fn bind(&self, unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.id);
+    }
+}
+
+impl Drop for Cubemap {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.id) }
+    }
+}
+
+// Direction through texel (s, t) of a cube face, both in [-1, 1] from the
+// top left corner, following the GL cubemap face orientation.
+fn cube_face_direction(face: usize, s: f32, t: f32) -> glam::Vec3 {
+    match face {
+        0 => glam::vec3(1.0, -t, -s),
+        1 => glam::vec3(-1.0, -t, s),
+        2 => glam::vec3(s, 1.0, t),
+        3 => glam::vec3(s, -1.0, -t),
+        4 => glam::vec3(s, -t, 1.0),
+        _ => glam::vec3(-s, -t, -1.0),
+    }
+    .normalize()
+}
+
+fn sample_equirect(src: &image::Rgba32FImage, dir: glam::Vec3) -> image::Rgba<f32> {
+    let u = 0.5 + dir.z.atan2(dir.x) / (2.0 * std::f32::consts::PI);
+    let v = 0.5 - dir.y.asin() / std::f32::consts::PI;
+    // bilinear filtering, wrapping horizontally and clamping vertically
+    let x = u * src.width() as f32 - 0.5;
+    let y = (v * src.height() as f32 - 0.5).clamp(0.0, (src.height() - 1) as f32);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(src.width() as i64) as u32;
+        let y = (y as u32).min(src.height() - 1);
+        glam::Vec4::from_array(src.get_pixel(x, y).0)
+    };
+    let top = texel(x0, y0).lerp(texel(x0 + 1.0, y0), fx);
+    let bottom = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), fx);
+    image::Rgba(top.lerp(bottom, fy).to_array())
+}
+
+// Draws a cubemap behind everything else. Call it after the scene so only
+// pixels not covered by geometry get shaded.
+pub struct Skybox {
+    vao: u32,
+    program: ShaderProgram,
+}
+
+impl Skybox {
+    pub unsafe fn new() -> Result<Self, String> {
+        let vertex_shader = Shader::from_str(
+            include_str!("./shaders/vertex_shader_skybox.glsl"),
+            ShaderType::VertexShader,
+        )?;
+        let fragment_shader = Shader::from_str(
+            include_str!("./shaders/fragment_shader_skybox.glsl"),
+            ShaderType::FragmentShader,
+        )?;
+        let mut program = ShaderProgram::new();
+        program.attach_shader(vertex_shader);
+        program.attach_shader(fragment_shader);
+        program.link_program()?;
+        program.set_uniform_i32("skybox", 0)?;
+
+        #[rustfmt::skip]
+        let verts: [f32; 108] = [
+            -1.0,  1.0, -1.0,  -1.0, -1.0, -1.0,   1.0, -1.0, -1.0,
+             1.0, -1.0, -1.0,   1.0,  1.0, -1.0,  -1.0,  1.0, -1.0,
+
+            -1.0, -1.0,  1.0,  -1.0, -1.0, -1.0,  -1.0,  1.0, -1.0,
+            -1.0,  1.0, -1.0,  -1.0,  1.0,  1.0,  -1.0, -1.0,  1.0,
+
+             1.0, -1.0, -1.0,   1.0, -1.0,  1.0,   1.0,  1.0,  1.0,
+             1.0,  1.0,  1.0,   1.0,  1.0, -1.0,   1.0, -1.0, -1.0,
+
+            -1.0, -1.0,  1.0,  -1.0,  1.0,  1.0,   1.0,  1.0,  1.0,
+             1.0,  1.0,  1.0,   1.0, -1.0,  1.0,  -1.0, -1.0,  1.0,
+
+            -1.0,  1.0, -1.0,   1.0,  1.0, -1.0,   1.0,  1.0,  1.0,
+             1.0,  1.0,  1.0,  -1.0,  1.0,  1.0,  -1.0,  1.0, -1.0,
+
+            -1.0, -1.0, -1.0,  -1.0, -1.0,  1.0,   1.0, -1.0, -1.0,
+             1.0, -1.0, -1.0,  -1.0, -1.0,  1.0,   1.0, -1.0,  1.0
+        ];
+
+        let mut vao = 0;
+        let mut vbo = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            std::mem::size_of_val(&verts) as isize,
+            verts.as_ptr() as *const c_void,
+            gl::STATIC_DRAW,
+        );
+        // positions only, they double as the cubemap lookup direction
+        gl::VertexAttribPointer(
+            0,
+            3,
+            gl::FLOAT,
+            gl::FALSE,
+            (std::mem::size_of::<f32>() * 3) as i32,
+            std::ptr::null(),
+        );
+        gl::EnableVertexAttribArray(0);
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        gl::BindVertexArray(0);
+        check_gl_error()?;
+
+        Ok(Skybox { vao, program })
+    }
+
+    // Only the rotation of `view` is used so the sky never moves with the camera
+    pub unsafe fn draw(
+        &self,
+        cubemap: &Cubemap,
+        view: &glam::Mat4,
+        projection: &glam::Mat4,
+    ) -> Result<(), String> {
+        let rotation = glam::Mat4::from_mat3(glam::Mat3::from_mat4(*view));
+        self.program.use_program();
+        self.program.set_uniform_mat4("view", &rotation)?;
+        self.program.set_uniform_mat4("projection", projection)?;
+
+        // the vertex shader puts the sky on the far plane, which only passes
+        // the depth test with LEQUAL
+        let mut depth_func = 0;
+        gl::GetIntegerv(gl::DEPTH_FUNC, &mut depth_func);
+        gl::DepthFunc(gl::LEQUAL);
+        gl::BindVertexArray(self.vao);
+        cubemap.bind(0);
+        gl::DrawArrays(gl::TRIANGLES, 0, 36);
+        gl::BindVertexArray(0);
+        gl::DepthFunc(depth_func as u32);
+        check_gl_error()
+    }
+}
+
+// Reads a rectangle of the color buffer of `framebuffer`, 0 being the
+// default framebuffer. GL returns the bottom row first, the image is flipped
+// so it reads top row first.
+pub unsafe fn read_framebuffer_pixels(
+    framebuffer: u32,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+) -> Result<image::RgbaImage, String> {
+    let mut previous = 0;
+    gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous);
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+
+    let mut buffer = image::RgbaImage::new(width, height);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+        x,
+        y,
+        width as i32,
+        height as i32,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        buffer.as_mut_ptr() as *mut c_void,
+    );
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous as u32);
+    check_gl_error()?;
+    Ok(image::imageops::flip_vertical(&buffer))
+}
+
+// Saves what the currently bound read framebuffer shows inside the viewport
+// as a PNG. Call it before swapping buffers, the back buffer is undefined
+// afterwards.
+pub unsafe fn save_screenshot(path: &std::path::Path) -> Result<(), String> {
+    let mut framebuffer = 0;
+    gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut framebuffer);
+    let mut viewport = [0; 4];
+    gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+    let pixels = read_framebuffer_pixels(
+        framebuffer as u32,
+        viewport[0],
+        viewport[1],
+        viewport[2] as u32,
+        viewport[3] as u32,
+    )?;
+    // the window alpha is meaningless and would make the image transparent
+    let pixels = image::DynamicImage::ImageRgba8(pixels).into_rgb8();
+
+    if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    pixels
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to save screenshot {}: {}", path.display(), e))
+}
+
+// e.g. screenshot_20240131_235959_123.png, in UTC
+pub fn screenshot_file_name() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "screenshot_{:04}{:02}{:02}_{:02}{:02}{:02}_{:03}.png",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+// Days since 1970-01-01 to a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn load_texture_image(name: &str) -> Result<image::DynamicImage, String> {
+    load_image_file(&AssetResolver::new().texture(name)?)
+}
+
+fn load_image_file(path: &std::path::Path) -> Result<image::DynamicImage, String> {
+    decode_image(image::io::Reader::open(path).map_err(|x| x.to_string())?)
+}
+
+fn decode_image_bytes(bytes: &[u8]) -> Result<image::DynamicImage, String> {
+    let reader = image::io::Reader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|x| x.to_string())?;
+    decode_image(reader)
+}
+
+// Like `Reader::decode` but keeps Radiance HDR images as floats, the image
+// crate's generic decode path tone maps them down to 8 bits.
+fn decode_image<R: std::io::BufRead + std::io::Seek>(
+    reader: image::io::Reader<R>,
+) -> Result<image::DynamicImage, String> {
+    if reader.format() != Some(image::ImageFormat::Hdr) {
+        return reader.decode().map_err(|x| x.to_string());
+    }
+
+    let decoder =
+        image::codecs::hdr::HdrDecoder::new(reader.into_inner()).map_err(|x| x.to_string())?;
+    let meta = decoder.metadata();
+    let pixels = decoder.read_image_hdr().map_err(|x| x.to_string())?;
+    let buffer = image::Rgb32FImage::from_raw(
+        meta.width,
+        meta.height,
+        pixels.into_iter().flat_map(|x| x.0).collect(),
+    )
+    .ok_or("HDR image data does not match its dimensions")?;
+    Ok(image::DynamicImage::ImageRgb32F(buffer))
+}
+
+pub const ASSETS_ENV_VAR: &str = "LEARNOPENGL_ASSETS";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AssetKind {
+    Texture,
+    Shader,
+    Model,
+}
+
+impl AssetKind {
+    fn dir_name(self) -> &'static str {
+        match self {
+            AssetKind::Texture => "textures",
+            AssetKind::Shader => "shaders",
+            AssetKind::Model => "models",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AssetError {
+    NotFound {
+        kind: AssetKind,
+        name: String,
+        tried: Vec<std::path::PathBuf>,
+    },
+}
+
+impl std::fmt::Display for AssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetError::NotFound { kind, name, tried } => {
+                write!(f, "{:?} asset not found: {}, tried:", kind, name)?;
+                for path in tried {
+                    write!(f, "\n    {}", path.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+impl From<AssetError> for String {
+    fn from(err: AssetError) -> Self {
+        err.to_string()
+    }
+}
+
+// Finds asset files by searching a list of root directories in order. Every
+// root holds one sub directory per asset kind, e.g. `<root>/textures`.
+pub struct AssetResolver {
+    roots: Vec<std::path::PathBuf>,
+}
+
+impl AssetResolver {
+    // Searches the paths in $LEARNOPENGL_ASSETS, then the crate's `src`
+    // directory, then the directory of the executable.
+    pub fn new() -> Self {
+        AssetResolver {
+            roots: AssetResolver::default_roots(env::var_os(ASSETS_ENV_VAR).as_deref()),
+        }
+    }
+
+    // The roots `new` uses for `env_paths` as the value of $LEARNOPENGL_ASSETS
+    fn default_roots(env_paths: Option<&std::ffi::OsStr>) -> Vec<std::path::PathBuf> {
+        let mut roots = Vec::new();
+        if let Some(paths) = env_paths {
+            roots.extend(env::split_paths(paths));
+        }
+        roots.push(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src"));
+        if let Some(exe_dir) = env::current_exe()
+            .ok()
+            .and_then(|x| x.parent().map(|x| x.to_path_buf()))
+        {
+            roots.push(exe_dir);
+        }
+        roots
+    }
+
+    pub fn with_roots(roots: Vec<std::path::PathBuf>) -> Self {
+        AssetResolver { roots }
+    }
+
+    pub fn add_root(&mut self, root: impl Into<std::path::PathBuf>) {
+        self.roots.push(root.into());
+    }
+
+    pub fn roots(&self) -> &[std::path::PathBuf] {
+        &self.roots
+    }
+
+    pub fn resolve(&self, kind: AssetKind, name: &str) -> Result<std::path::PathBuf, AssetError> {
+        let mut tried = Vec::with_capacity(self.roots.len());
+        for root in &self.roots {
+            let path = root.join(kind.dir_name()).join(name);
+            if path.is_file() {
+                return Ok(path);
+            }
+            tried.push(path);
+        }
+        Err(AssetError::NotFound {
+            kind,
+            name: name.to_owned(),
+            tried,
+        })
+    }
+
+    pub fn texture(&self, name: &str) -> Result<std::path::PathBuf, AssetError> {
+        self.resolve(AssetKind::Texture, name)
+    }
+
+    pub fn shader(&self, name: &str) -> Result<std::path::PathBuf, AssetError> {
+        self.resolve(AssetKind::Shader, name)
+    }
+
+    pub fn model(&self, name: &str) -> Result<std::path::PathBuf, AssetError> {
+        self.resolve(AssetKind::Model, name)
+    }
+}
+
+impl Default for AssetResolver {
+    fn default() -> Self {
+        AssetResolver::new()
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct TextureKey {
+    path: std::path::PathBuf,
+    format: Option<PixelFormat>,
+    options: TextureOptions,
+}
+
+// Loads every texture file once per set of options and hands out shared
+// handles. A texture is deleted when the cache and every handle dropped it.
+pub struct TextureCache {
+    resolver: AssetResolver,
+    textures: std::collections::HashMap<TextureKey, std::rc::Rc<Texture>>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        TextureCache::with_resolver(AssetResolver::new())
+    }
+
+    pub fn with_resolver(resolver: AssetResolver) -> Self {
+        TextureCache {
+            resolver,
+            textures: std::collections::HashMap::new(),
+        }
+    }
+
+    pub unsafe fn get(
+        &mut self,
+        name: &str,
+        options: &TextureOptions,
+    ) -> Result<std::rc::Rc<Texture>, String> {
+        self.load(name, None, options)
+    }
+
+    pub unsafe fn get_with_format(
+        &mut self,
+        name: &str,
+        format: PixelFormat,
+        options: &TextureOptions,
+    ) -> Result<std::rc::Rc<Texture>, String> {
+        self.load(name, Some(format), options)
+    }
+
+    unsafe fn load(
+        &mut self,
+        name: &str,
+        format: Option<PixelFormat>,
+        options: &TextureOptions,
+    ) -> Result<std::rc::Rc<Texture>, String> {
+        let path = self.resolver.texture(name)?;
+        // different relative paths to the same file share one texture
+        let path = std::fs::canonicalize(&path).unwrap_or(path);
+        let key = TextureKey {
+            path,
+            format,
+            options: *options,
+        };
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+
+        let texture = std::rc::Rc::new(Texture::from_file(&key.path, format, options)?);
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    // Number of textures held by the cache
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    // Estimated GPU memory of all textures held by the cache
+    pub fn byte_size(&self) -> usize {
+        self.textures.values().map(|x| x.byte_size).sum()
+    }
+
+    // Releases textures nobody outside the cache holds a handle to
+    pub fn purge_unused(&mut self) {
+        self.textures
+            .retain(|_, texture| std::rc::Rc::strong_count(texture) > 1);
+    }
+
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+}
+
+impl Default for TextureCache {
+    fn default() -> Self {
+        TextureCache::new()
+    }
+}
+
+enum TextureSource {
+    File(std::path::PathBuf),
+    Bytes(&'static [u8]),
+}
+
+struct DecodeJob {
+    id: usize,
+    source: TextureSource,
+    format: Option<PixelFormat>,
+}
+
+// Pixels ready for `TexImage2D`, already flipped and converted on a worker
+struct DecodedTexture {
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    pixels: Vec<u8>,
+}
+
+impl DecodeJob {
+    fn decode(self) -> Result<DecodedTexture, String> {
+        let img = match self.source {
+            TextureSource::File(path) => load_image_file(&path)?,
+            TextureSource::Bytes(bytes) => decode_image_bytes(bytes)?,
+        }
+        .flipv();
+        let format = self.format.unwrap_or_else(|| PixelFormat::from_image(&img));
+        let (width, height) = (img.width(), img.height());
+        Ok(DecodedTexture {
+            width,
+            height,
+            format,
+            pixels: image_to_pixels(img, format)?,
+        })
+    }
+}
+
+enum AsyncTextureState {
+    Pending,
+    Loaded(std::rc::Rc<Texture>),
+    Failed(String),
+}
+
+// Handle to a texture that is still being loaded. Until the upload happened
+// it hands out the loader's placeholder texture.
+#[derive(Clone)]
+pub struct AsyncTexture {
+    state: std::rc::Rc<std::cell::RefCell<AsyncTextureState>>,
+    placeholder: std::rc::Rc<Texture>,
+}
+
+impl AsyncTexture {
+    pub fn texture(&self) -> std::rc::Rc<Texture> {
+        match &*self.state.borrow() {
+            AsyncTextureState::Loaded(texture) => texture.clone(),
+            _ => self.placeholder.clone(),
+        }
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        matches!(&*self.state.borrow(), AsyncTextureState::Loaded(_))
+    }
+
+    pub fn error(&self) -> Option<String> {
+        match &*self.state.borrow() {
+            AsyncTextureState::Failed(err) => Some(err.clone()),
+            _ => None,
+        }
+    }
+
+    pub unsafe fn bind(&self, unit: u32) {
+        self.texture().bind(unit);
+    }
+}
+
+// Decodes textures on worker threads. The GL calls still have to happen on
+// the thread owning the context, which `pump_uploads` does once per frame.
+pub struct AsyncTextureLoader {
+    jobs: Option<std::sync::mpsc::Sender<DecodeJob>>,
+    decoded: std::sync::mpsc::Receiver<(usize, Result<DecodedTexture, String>)>,
+    workers: Vec<std::thread::JoinHandle<()>>,
+    pending: std::collections::HashMap<usize, (AsyncTexture, TextureOptions)>,
+    next_id: usize,
+    resolver: AssetResolver,
+    placeholder: std::rc::Rc<Texture>,
+}
+
+impl AsyncTextureLoader {
+    pub unsafe fn new(worker_count: usize) -> Result<Self, String> {
+        let (jobs, job_receiver) = std::sync::mpsc::channel::<DecodeJob>();
+        let (decoded_sender, decoded) = std::sync::mpsc::channel();
+        let job_receiver = std::sync::Arc::new(std::sync::Mutex::new(job_receiver));
+
+        let workers = (0..worker_count.max(1))
+            .map(|idx| {
+                let job_receiver = job_receiver.clone();
+                let decoded_sender = decoded_sender.clone();
+                std::thread::Builder::new()
+                    .name(format!("texture decoder {}", idx))
+                    .spawn(move || loop {
+                        // the lock guard is dropped before decoding starts
+                        let job = job_receiver.lock().unwrap().recv();
+                        let Ok(job) = job else { break };
+                        let id = job.id;
+                        if decoded_sender.send((id, job.decode())).is_err() {
+                            break;
+                        }
+                    })
+                    .map_err(|x| x.to_string())
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        // magenta and black checkers make missing textures easy to spot
+        let checker = image::RgbImage::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgb([255, 0, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        let placeholder = Texture::from_image(
+            image::DynamicImage::ImageRgb8(checker),
+            &TextureOptions::pixel_art().wrap(WrapMode::Repeat),
+        )?;
+
+        Ok(AsyncTextureLoader {
+            jobs: Some(jobs),
+            decoded,
+            workers,
+            pending: std::collections::HashMap::new(),
+            next_id: 0,
+            resolver: AssetResolver::new(),
+            placeholder: std::rc::Rc::new(placeholder),
+        })
+    }
+
+    pub fn load(&mut self, name: &str, options: &TextureOptions) -> Result<AsyncTexture, String> {
+        let path = self.resolver.texture(name)?;
+        Ok(self.queue(TextureSource::File(path), None, options))
+    }
+
+    pub fn load_with_format(
+        &mut self,
+        name: &str,
+        format: PixelFormat,
+        options: &TextureOptions,
+    ) -> Result<AsyncTexture, String> {
+        let path = self.resolver.texture(name)?;
+        Ok(self.queue(TextureSource::File(path), Some(format), options))
+    }
+
+    // Decodes an encoded image embedded in the binary
+    pub fn load_bytes(&mut self, bytes: &'static [u8], options: &TextureOptions) -> AsyncTexture {
+        self.queue(TextureSource::Bytes(bytes), None, options)
+    }
+
+    fn queue(
+        &mut self,
+        source: TextureSource,
+        format: Option<PixelFormat>,
+        options: &TextureOptions,
+    ) -> AsyncTexture {
+        let id = self.next_id;
+        self.next_id += 1;
+        let texture = AsyncTexture {
+            state: std::rc::Rc::new(std::cell::RefCell::new(AsyncTextureState::Pending)),
+            placeholder: self.placeholder.clone(),
+        };
+        let job = DecodeJob { id, source, format };
+        match self.jobs.as_ref().map(|jobs| jobs.send(job)) {
+            Some(Ok(())) => {
+                self.pending.insert(id, (texture.clone(), *options));
+            }
+            _ => {
+                *texture.state.borrow_mut() =
+                    AsyncTextureState::Failed("Texture decoder threads stopped".to_owned());
+            }
+        }
+        texture
+    }
+
+    // Uploads decoded textures until `budget` is used up, at least one per
+    // call so loading always makes progress. Returns the number uploaded.
+    pub unsafe fn pump_uploads(&mut self, budget: std::time::Duration) -> usize {
+        let start = std::time::Instant::now();
+        let mut uploaded = 0;
+        while uploaded == 0 || start.elapsed() < budget {
+            let Ok((id, decoded)) = self.decoded.try_recv() else {
+                break;
+            };
+            let Some((texture, options)) = self.pending.remove(&id) else {
+                continue;
+            };
+            let state = decoded.and_then(|x| {
+                Texture::upload(x.width, x.height, x.format, Some(&x.pixels), &options)
+            });
+            *texture.state.borrow_mut() = match state {
+                Ok(loaded) => AsyncTextureState::Loaded(std::rc::Rc::new(loaded)),
+                Err(err) => AsyncTextureState::Failed(err),
+            };
+            uploaded += 1;
+        }
+        uploaded
+    }
+
+    // Number of textures not uploaded yet
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Blocks until every queued texture is uploaded or has failed, for runs
+    // that need the final images in the first frame
+    pub unsafe fn wait_for_uploads(&mut self) {
+        while self.pending() > 0 {
+            if self.pump_uploads(std::time::Duration::from_millis(4)) == 0 {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+    }
+
+    pub fn placeholder(&self) -> std::rc::Rc<Texture> {
+        self.placeholder.clone()
+    }
+}
+
+impl Drop for AsyncTextureLoader {
+    fn drop(&mut self) {
+        // closing the job channel lets the workers finish their loops
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+pub enum DepthAttachment {
+    None,
+    // 24 bit depth and 8 bit stencil, can't be sampled
+    Renderbuffer,
+    // sampleable depth, e.g. for shadow maps
+    Texture(PixelFormat),
+}
+
+// Off screen render target. Color attachments are textures so they can be
+// sampled by later passes, except for multisampled framebuffers which use
+// renderbuffers and have to be resolved into another framebuffer first.
+pub struct Framebuffer {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub color_attachments: Vec<Texture>,
+    pub depth_texture: Option<Texture>,
+    renderbuffers: Vec<u32>,
+    // multisampled color attachments are renderbuffers, so
+    // `color_attachments` doesn't say how many there are
+    color_attachment_count: u32,
+    // state replaced by `bind` and put back by `unbind`, the read and draw
+    // framebuffers
+    previous_framebuffers: std::cell::Cell<[u32; 2]>,
+    previous_viewport: std::cell::Cell<[i32; 4]>,
+}
+
+impl Framebuffer {
+    pub unsafe fn new(
+        width: u32,
+        height: u32,
+        color_formats: &[PixelFormat],
+        depth: DepthAttachment,
+    ) -> Result<Self, String> {
+        Framebuffer::new_multisampled(width, height, 1, color_formats, depth)
+    }
+
+    // `samples` of 1 creates a regular framebuffer
+    pub unsafe fn new_multisampled(
+        width: u32,
+        height: u32,
+        samples: u32,
+        color_formats: &[PixelFormat],
+        depth: DepthAttachment,
+    ) -> Result<Self, String> {
+        check_sample_count(samples)?;
+        let options = TextureOptions::default()
+            .wrap(WrapMode::ClampToEdge)
+            .mipmaps(false);
+
+        let previous_framebuffers = framebuffer_bindings();
+        let mut id = 0;
+        gl::GenFramebuffers(1, &mut id);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, id);
+
+        // fill in the struct first so everything created so far is deleted
+        // when a later step fails
+        let mut framebuffer = Framebuffer {
+            id,
+            width,
+            height,
+            samples,
+            color_attachments: Vec::with_capacity(color_formats.len()),
+            depth_texture: None,
+            renderbuffers: Vec::new(),
+            color_attachment_count: color_formats.len() as u32,
+            previous_framebuffers: std::cell::Cell::new([0; 2]),
+            previous_viewport: std::cell::Cell::new([0; 4]),
+        };
+        let result = framebuffer.attach(color_formats, depth, &options);
+        restore_framebuffer_bindings(previous_framebuffers);
+        result?;
+        Ok(framebuffer)
+    }
+
+    unsafe fn attach(
+        &mut self,
+        color_formats: &[PixelFormat],
+        depth: DepthAttachment,
+        options: &TextureOptions,
+    ) -> Result<(), String> {
+        for (idx, format) in color_formats.iter().enumerate() {
+            if format.is_depth() {
+                return Err(format!(
+                    "Color attachment {} can't use depth format {:?}",
+                    idx, format
+                ));
+            }
+            let attachment = gl::COLOR_ATTACHMENT0 + idx as u32;
+            if self.samples > 1 {
+                self.attach_renderbuffer(attachment, format.internal_format());
+                continue;
+            }
+            let texture = Texture::empty(self.width, self.height, *format, options)?;
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, gl::TEXTURE_2D, texture.id, 0);
+            self.color_attachments.push(texture);
+        }
+
+        match depth {
+            DepthAttachment::None => {}
+            DepthAttachment::Renderbuffer => {
+                self.attach_renderbuffer(gl::DEPTH_STENCIL_ATTACHMENT, gl::DEPTH24_STENCIL8);
+            }
+            DepthAttachment::Texture(_) if self.samples > 1 => {
+                return Err(
+                    "Multisampled framebuffers only support renderbuffer depth attachments"
+                        .to_owned(),
+                );
+            }
+            DepthAttachment::Texture(format) => {
+                if !format.is_depth() {
+                    return Err(format!(
+                        "Depth attachment needs a depth format, got {:?}",
+                        format
+                    ));
+                }
+                let texture = Texture::empty(self.width, self.height, format, options)?;
+                let attachment = if format == PixelFormat::Depth24Stencil8 {
+                    gl::DEPTH_STENCIL_ATTACHMENT
+                } else {
+                    gl::DEPTH_ATTACHMENT
+                };
+                gl::FramebufferTexture2D(
+                    gl::FRAMEBUFFER,
+                    attachment,
+                    gl::TEXTURE_2D,
+                    texture.id,
+                    0,
+                );
+                self.depth_texture = Some(texture);
+            }
+        }
+
+        if color_formats.is_empty() {
+            // depth only, e.g. a shadow map
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+        } else {
+            let draw_buffers: Vec<u32> = (0..color_formats.len() as u32)
+                .map(|x| gl::COLOR_ATTACHMENT0 + x)
+                .collect();
+            gl::DrawBuffers(draw_buffers.len() as i32, draw_buffers.as_ptr());
+        }
+
+        check_gl_error()?;
+        check_framebuffer_status(gl::FRAMEBUFFER)
+    }
+
+    unsafe fn attach_renderbuffer(&mut self, attachment: u32, internal_format: u32) {
+        let mut renderbuffer = 0;
+        gl::GenRenderbuffers(1, &mut renderbuffer);
+        self.renderbuffers.push(renderbuffer);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+        // a sample count of 1 would still make a multisampled renderbuffer,
+        // which can't be mixed with the texture attachments
+        let samples = if self.samples > 1 { self.samples } else { 0 };
+        gl::RenderbufferStorageMultisample(
+            gl::RENDERBUFFER,
+            samples as i32,
+            internal_format,
+            self.width as i32,
+            self.height as i32,
+        );
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, renderbuffer);
+    }
+
+    // Blits every color attachment into the one with the same index in
+    // `target`, averaging the samples. Both need the same size. The default
+    // framebuffer, when `target` is None, only takes a single attachment.
+    pub unsafe fn resolve(&self, target: Option<&Framebuffer>) -> Result<(), String> {
+        let count = self.color_attachment_count;
+        match target {
+            None if count > 1 => {
+                return Err(format!(
+                    "Can't resolve {} color attachments into the default framebuffer",
+                    count
+                ))
+            }
+            Some(target) if target.color_attachment_count < count => {
+                return Err(format!(
+                    "Can't resolve {} color attachments into a framebuffer with {}",
+                    count, target.color_attachment_count
+                ))
+            }
+            _ => {}
+        }
+
+        let previous_framebuffers = framebuffer_bindings();
+
+        let (target_id, target_width, target_height) =
+            target.map_or((0, self.width, self.height), |x| (x.id, x.width, x.height));
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target_id);
+        for idx in 0..count {
+            let attachment = gl::COLOR_ATTACHMENT0 + idx;
+            gl::ReadBuffer(attachment);
+            if target.is_some() {
+                gl::DrawBuffers(1, &attachment);
+            }
+            gl::BlitFramebuffer(
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                0,
+                0,
+                target_width as i32,
+                target_height as i32,
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            );
+        }
+        // put back the read and draw buffers `attach` set up
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        if let Some(target) = target {
+            let draw_buffers: Vec<u32> = (0..target.color_attachment_count)
+                .map(|x| gl::COLOR_ATTACHMENT0 + x)
+                .collect();
+            gl::DrawBuffers(draw_buffers.len() as i32, draw_buffers.as_ptr());
+        }
+        restore_framebuffer_bindings(previous_framebuffers);
+        check_gl_error()
+    }
+
+    // Renders into this framebuffer until `unbind` is called
+    pub unsafe fn bind(&self) {
+        let mut previous_viewport = [0; 4];
+        gl::GetIntegerv(gl::VIEWPORT, previous_viewport.as_mut_ptr());
+        self.previous_framebuffers.set(framebuffer_bindings());
+        self.previous_viewport.set(previous_viewport);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+        gl::Viewport(0, 0, self.width as i32, self.height as i32);
+    }
+
+    // Goes back to the framebuffers and viewport active before `bind`
+    pub unsafe fn unbind(&self) {
+        let [x, y, width, height] = self.previous_viewport.get();
+        restore_framebuffer_bindings(self.previous_framebuffers.get());
+        gl::Viewport(x, y, width, height);
+    }
+
+    pub fn color_attachment(&self, idx: usize) -> Option<&Texture> {
+        self.color_attachments.get(idx)
+    }
+
+    pub unsafe fn read_pixels(&self, attachment: usize) -> Result<image::RgbaImage, String> {
+        if self.samples > 1 {
+            return Err("Multisampled framebuffers have to be resolved before reading".to_owned());
+        }
+        if attachment >= self.color_attachments.len() {
+            return Err(format!(
+                "Framebuffer {} has no color attachment {}",
+                self.id, attachment
+            ));
+        }
+        let mut previous = 0;
+        gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0 + attachment as u32);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous as u32);
+        read_framebuffer_pixels(self.id, 0, 0, self.width, self.height)
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.id);
+            gl::DeleteRenderbuffers(self.renderbuffers.len() as i32, self.renderbuffers.as_ptr());
+        }
+    }
+}
+
+// Sample counts offered as MSAA settings, 1 turns multisampling off
+// The read and draw framebuffer bindings, they can differ after a blit
+unsafe fn framebuffer_bindings() -> [u32; 2] {
+    let mut read = 0;
+    let mut draw = 0;
+    gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut read);
+    gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut draw);
+    [read as u32, draw as u32]
+}
+
+unsafe fn restore_framebuffer_bindings([read, draw]: [u32; 2]) {
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, read);
+    gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, draw);
+}
+
+pub const MSAA_SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+pub unsafe fn max_samples() -> u32 {
+    let mut samples = 0;
+    gl::GetIntegerv(gl::MAX_SAMPLES, &mut samples);
+    samples as u32
+}
+
+pub unsafe fn check_sample_count(samples: u32) -> Result<(), String> {
+    if !MSAA_SAMPLE_COUNTS.contains(&samples) {
+        return Err(format!(
+            "Unsupported sample count {}, expected one of {:?}",
+            samples, MSAA_SAMPLE_COUNTS
+        ));
+    }
+    if samples > 1 && samples > max_samples() {
+        return Err(format!(
+            "{} samples requested but the driver supports at most {}",
+            samples,
+            max_samples()
+        ));
+    }
+    Ok(())
+}
+
+fn framebuffer_status_to_error(status: gl::types::GLenum) -> String {
+    match status {
+        gl::FRAMEBUFFER_UNDEFINED => {
+            "GL_FRAMEBUFFER_UNDEFINED: the default framebuffer does not exist".to_owned()
+        }
+        gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => {
+            "GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT: an attachment is not renderable or has no size"
+                .to_owned()
+        }
+        gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => {
+            "GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT: no image is attached".to_owned()
+        }
+        gl::FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER => {
+            "GL_FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER: a draw buffer has no attachment".to_owned()
+        }
+        gl::FRAMEBUFFER_INCOMPLETE_READ_BUFFER => {
+            "GL_FRAMEBUFFER_INCOMPLETE_READ_BUFFER: the read buffer has no attachment".to_owned()
+        }
+        gl::FRAMEBUFFER_UNSUPPORTED => {
+            "GL_FRAMEBUFFER_UNSUPPORTED: the driver does not support this combination of formats"
+                .to_owned()
+        }
+        gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => {
+            "GL_FRAMEBUFFER_INCOMPLETE_MULTISAMPLE: attachments have different sample counts"
+                .to_owned()
+        }
+        gl::FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS => {
+            "GL_FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS: attachments are not all layered".to_owned()
+        }
+        _ => format!("Unknown framebuffer status {}", status),
+    }
+}
+
+// Checks the framebuffer bound to `target` is complete
+pub unsafe fn check_framebuffer_status(target: u32) -> Result<(), String> {
+    let status = gl::CheckFramebufferStatus(target);
+    if status == gl::FRAMEBUFFER_COMPLETE {
+        Ok(())
+    } else {
+        Err(framebuffer_status_to_error(status))
+    }
+}
+
+// OpenGL context without a window or display server, for CI machines.
+// Uses EGL's surfaceless platform, so Mesa falls back to llvmpipe when there
+// is no GPU. Render into a `Framebuffer`, the context has no default one.
+#[cfg(feature = "headless")]
+pub struct HeadlessContext {
+    egl: khronos_egl::Instance<khronos_egl::Static>,
+    display: khronos_egl::Display,
+    context: khronos_egl::Context,
+}
+
+#[cfg(feature = "headless")]
+impl HeadlessContext {
+    // Creates a core profile context, makes it current and loads the GL
+    // function pointers
+    pub unsafe fn new(major: i32, minor: i32) -> Result<Self, String> {
+        if env::var_os("EGL_PLATFORM").is_none() {
+            env::set_var("EGL_PLATFORM", "surfaceless");
+        }
+        let egl = khronos_egl::Instance::new(khronos_egl::Static);
+        let display = egl
+            .get_display(khronos_egl::DEFAULT_DISPLAY)
+            .ok_or_else(|| "No EGL display available".to_owned())?;
+        egl.initialize(display)
+            .map_err(|e| format!("Failed to initialize EGL: {}", e))?;
+        egl.bind_api(khronos_egl::OPENGL_API)
+            .map_err(|e| format!("EGL has no desktop OpenGL support: {}", e))?;
+
+        let config_attribs = [
+            khronos_egl::SURFACE_TYPE,
+            khronos_egl::PBUFFER_BIT,
+            khronos_egl::RENDERABLE_TYPE,
+            khronos_egl::OPENGL_BIT,
+            khronos_egl::NONE,
+        ];
+        let config = egl
+            .choose_first_config(display, &config_attribs)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "No EGL config supports OpenGL".to_owned())?;
+        let context_attribs = [
+            khronos_egl::CONTEXT_MAJOR_VERSION,
+            major,
+            khronos_egl::CONTEXT_MINOR_VERSION,
+            minor,
+            khronos_egl::CONTEXT_OPENGL_PROFILE_MASK,
+            khronos_egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            khronos_egl::NONE,
+        ];
+        let context = egl
+            .create_context(display, config, None, &context_attribs)
+            .map_err(|e| format!("Failed to create OpenGL {}.{} context: {}", major, minor, e))?;
+        egl.make_current(display, None, None, Some(context))
+            .map_err(|e| format!("Failed to make the context current: {}", e))?;
+
+        gl::load_with(|name| {
+            egl.get_proc_address(name)
+                .map_or(std::ptr::null(), |f| f as *const c_void)
+        });
+        Ok(HeadlessContext {
+            egl,
+            display,
+            context,
+        })
+    }
+}
+
+#[cfg(feature = "headless")]
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
+
+pub struct Shader {
+    pub id: u32,
+}
+
+impl Shader {
+    // Reads `path` and resolves its includes from the `shaders` directory of
+    // every asset root
+    pub unsafe fn from_file(
+        path: impl AsRef<std::path::Path>,
+        shader_type: ShaderType,
+    ) -> Result<Self, ShaderError> {
+        let shader = ShaderPreprocessor::new()
+            .process(path.as_ref())
+            .map_err(|e| ShaderError::new(shader_type, e))?;
+        Shader::from_preprocessed(&shader, shader_type)
+    }
+
+    // Errors point into the original files instead of the expanded source
+    pub unsafe fn from_preprocessed(
+        shader: &PreprocessedShader,
+        shader_type: ShaderType,
+    ) -> Result<Self, ShaderError> {
+        Shader::compile(&shader.source, shader_type, &shader.files, &shader.texts)
+    }
+
+    pub unsafe fn from_str(source: &str, shader_type: ShaderType) -> Result<Self, ShaderError> {
+        Shader::compile(source, shader_type, &[], &[source.to_owned()])
+    }
+
+    unsafe fn compile(
+        source: &str,
+        shader_type: ShaderType,
+        files: &[std::path::PathBuf],
+        texts: &[String],
+    ) -> Result<Self, ShaderError> {
+        shader_type
+            .check_supported()
+            .map_err(|e| ShaderError::new(shader_type, e))?;
+        let c_shader_source = std::ffi::CString::new(source)
+            .map_err(|e| ShaderError::new(shader_type, e.to_string()))?;
+        let shader = Shader {
+            id: gl::CreateShader(shader_type as u32),
+        };
+
+        gl::ShaderSource(
+            shader.id,
+            1,
+            &(c_shader_source.as_ptr() as *const i8) as *const *const i8,
+            std::ptr::null(),
+        );
+        gl::CompileShader(shader.id);
+        check_shader_compile_errors(shader.id)
+            .map_err(|log| ShaderError::from_log(shader_type, &log, files, texts))?;
+        Ok(shader)
+    }
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteShader(self.id) }
+    }
+}
+
+// Lines of source shown around each compile error
+const SHADER_SNIPPET_CONTEXT: u32 = 2;
+
+// One entry of a driver's compile log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderDiagnostic {
+    // Source string number from the log, the index into the files of a
+    // `PreprocessedShader`
+    pub source: u32,
+    pub file: Option<std::path::PathBuf>,
+    pub line: Option<u32>,
+    // Starts at 1, only Mesa reports columns
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+impl ShaderDiagnostic {
+    // Understands the Mesa, NVIDIA and AMD/Intel formats:
+    //
+    //     0:12(5): error: `foo' undeclared
+    //     0(12) : error C1008: undefined variable "foo"
+    //     ERROR: 0:12: 'foo' : undeclared identifier
+    fn parse(line: &str) -> Option<ShaderDiagnostic> {
+        let located = |source: &str, line: &str, column: Option<&str>, message: String| {
+            Some(ShaderDiagnostic {
+                source: source.trim().parse().ok()?,
+                file: None,
+                line: Some(line.trim().parse().ok()?),
+                column: match column {
+                    Some(column) => Some(column.parse().ok()?),
+                    None => None,
+                },
+                message,
+            })
+        };
+        let mesa = || {
+            let (source, rest) = line.split_once(':')?;
+            let (line, rest) = rest.split_once('(')?;
+            let (column, message) = rest.split_once("): ")?;
+            located(source, line, Some(column), message.to_owned())
+        };
+        let nvidia = || {
+            let (source, rest) = line.split_once('(')?;
+            let (line, message) = rest.split_once(") : ")?;
+            located(source, line, None, message.to_owned())
+        };
+        let amd = || {
+            let (severity, rest) = line.split_once(": ")?;
+            if severity != "ERROR" && severity != "WARNING" {
+                return None;
+            }
+            let (source, rest) = rest.split_once(':')?;
+            let (line, message) = rest.split_once(": ")?;
+            let message = format!("{}: {}", severity.to_lowercase(), message);
+            located(source, line, None, message)
+        };
+        mesa().or_else(nvidia).or_else(amd)
+    }
+}
+
+impl std::fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(line) = self.line else {
+            return f.write_str(&self.message);
+        };
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file.display(), line)?,
+            None => write!(f, "source {}:{}", self.source, line)?,
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// Compiling one stage failed. Displays every diagnostic with the source
+// lines around it.
+#[derive(Debug)]
+pub struct ShaderError {
+    pub stage: ShaderType,
+    pub diagnostics: Vec<ShaderDiagnostic>,
+    // The driver's log as it was returned
+    pub log: String,
+    // text of each source string number for the snippets
+    sources: Vec<String>,
+}
+
+impl ShaderError {
+    // A failure without a compile log, e.g. a missing include
+    pub fn new(stage: ShaderType, message: impl Into<String>) -> Self {
+        let message = message.into();
+        ShaderError {
+            stage,
+            diagnostics: vec![ShaderDiagnostic {
+                source: 0,
+                file: None,
+                line: None,
+                column: None,
+                message: message.clone(),
+            }],
+            log: message,
+            sources: Vec::new(),
+        }
+    }
+
+    // Splits the log into diagnostics. Lines in no known format are kept
+    // without a location so nothing from the driver gets lost. `texts` has
+    // the contents of each source string number for the snippets, `files`
+    // their paths if they came from files.
+    pub fn from_log(
+        stage: ShaderType,
+        log: &str,
+        files: &[std::path::PathBuf],
+        texts: &[String],
+    ) -> Self {
+        let diagnostics = log
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut diagnostic = ShaderDiagnostic::parse(line).unwrap_or(ShaderDiagnostic {
+                    source: 0,
+                    file: None,
+                    line: None,
+                    column: None,
+                    message: line.to_owned(),
+                });
+                if diagnostic.line.is_some() {
+                    diagnostic.file = files.get(diagnostic.source as usize).cloned();
+                }
+                diagnostic
+            })
+            .collect();
+        ShaderError {
+            stage,
+            diagnostics,
+            log: log.to_owned(),
+            sources: texts.to_vec(),
+        }
+    }
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to compile {}", self.stage)?;
+        for diagnostic in &self.diagnostics {
+            write!(f, "\n{}", diagnostic)?;
+            let Some(line) = diagnostic.line else {
+                continue;
+            };
+            let first = line.saturating_sub(SHADER_SNIPPET_CONTEXT).max(1);
+            let last = line + SHADER_SNIPPET_CONTEXT;
+            let width = last.to_string().len();
+            for number in first..=last {
+                let Some(text) = self
+                    .sources
+                    .get(diagnostic.source as usize)
+                    .and_then(|source| source.lines().nth(number as usize - 1))
+                else {
+                    continue;
+                };
+                let marker = if number == line { '>' } else { ' ' };
+                write!(f, "\n{}{:>width$} | {}", marker, number, text)?;
+                if let Some(column) = diagnostic.column.filter(|_| number == line) {
+                    // keep tabs so the caret lines up with the text above
+                    let padding: String = text
+                        .chars()
+                        .take(column.saturating_sub(1) as usize)
+                        .map(|c| if c == '\t' { '\t' } else { ' ' })
+                        .collect();
+                    write!(f, "\n {:>width$} | {}^", "", padding)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+impl From<ShaderError> for String {
+    fn from(err: ShaderError) -> Self {
+        err.to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct PreprocessedShader {
+    pub source: String,
+    // Every file that went into `source`. The index is the source string
+    // number used by the `#line` directives and the driver's compile log.
+    pub files: Vec<std::path::PathBuf>,
+    // Contents of each of `files` as it was read, compile errors show their
+    // lines from here
+    pub texts: Vec<String>,
+}
+
+// Shaders built into the binary so it runs without the `shaders` directory
+const EMBEDDED_SHADERS: [(&str, &str); 6] = [
+    (
+        "vertex_shader.glsl",
+        include_str!("./shaders/vertex_shader.glsl"),
+    ),
+    (
+        "fragment_shader.glsl",
+        include_str!("./shaders/fragment_shader.glsl"),
+    ),
+    (
+        "vertex_shader_light.glsl",
+        include_str!("./shaders/vertex_shader_light.glsl"),
+    ),
+    (
+        "fragment_shader_light.glsl",
+        include_str!("./shaders/fragment_shader_light.glsl"),
+    ),
+    (
+        "common/lighting.glsl",
+        include_str!("./shaders/common/lighting.glsl"),
+    ),
+    (
+        "common/camera.glsl",
+        include_str!("./shaders/common/camera.glsl"),
+    ),
+];
+
+// Expands `#include "common/lighting.glsl"` lines relative to the shader
+// roots. A file is pasted in every time it is included unless it has a
+// `#pragma once` line, which shared struct definitions need. Files that
+// aren't on disk come from the in-memory copies added with `embed`. Defines
+// are injected right after `#version` to build variants of the same shader.
+pub struct ShaderPreprocessor {
+    roots: Vec<std::path::PathBuf>,
+    defines: Vec<String>,
+    embedded: Vec<(String, String)>,
+}
+
+impl ShaderPreprocessor {
+    // The `shaders` directories of the default asset roots, falling back to
+    // the copies of the crate's shaders built into the binary
+    pub fn new() -> Self {
+        let roots = AssetResolver::new()
+            .roots()
+            .iter()
+            .map(|x| x.join(AssetKind::Shader.dir_name()))
+            .collect();
+        EMBEDDED_SHADERS.iter().fold(
+            ShaderPreprocessor {
+                roots,
+                defines: Vec::new(),
+                embedded: Vec::new(),
+            },
+            |preprocessor, (name, source)| preprocessor.embed(*name, *source),
+        )
+    }
+
+    pub fn with_root(root: impl Into<std::path::PathBuf>) -> Self {
+        ShaderPreprocessor {
+            roots: vec![root.into()],
+            defines: Vec::new(),
+            embedded: Vec::new(),
+        }
+    }
+
+    // Used for `name`, as an include or a path passed to `process`, when no
+    // such file exists on disk
+    pub fn embed(mut self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        self.embedded.push((name.into(), source.into()));
+        self
+    }
+
+    // Adds `#define name 1` to every processed shader
+    pub fn define(mut self, name: impl Into<String>) -> Self {
+        self.defines.push(name.into());
+        self
+    }
+
+    pub fn roots(&self) -> &[std::path::PathBuf] {
+        &self.roots
+    }
+
+    pub fn defines(&self) -> &[String] {
+        &self.defines
+    }
+
+    pub fn process(&self, path: &std::path::Path) -> Result<PreprocessedShader, String> {
+        let mut shader = PreprocessedShader {
+            source: String::new(),
+            files: Vec::new(),
+            texts: Vec::new(),
+        };
+        let (path, source) = match self.read(path) {
+            Ok(file) => file,
+            Err(e) => self.find_embedded(path).ok_or(e)?,
+        };
+        self.process_file(path, &source, &mut shader, &mut Vec::new())?;
+        // without `#version` the defines can simply go first
+        let has_version = shader
+            .source
+            .lines()
+            .any(|line| line.trim_start().starts_with("#version"));
+        if !self.defines.is_empty() && !has_version {
+            shader.source = format!("{}#line 1 0\n{}", self.define_lines(), shader.source);
+        }
+        Ok(shader)
+    }
+
+    fn define_lines(&self) -> String {
+        self.defines
+            .iter()
+            .map(|name| format!("#define {} 1\n", name))
+            .collect()
+    }
+
+    // The canonical path and contents of a file on disk
+    fn read(&self, path: &std::path::Path) -> Result<(std::path::PathBuf, String), String> {
+        let error = |e| format!("Failed to read shader {}: {}", path.display(), e);
+        let path = path.canonicalize().map_err(error)?;
+        let source = std::fs::read_to_string(&path).map_err(error)?;
+        Ok((path, source))
+    }
+
+    fn find_embedded(&self, name: &std::path::Path) -> Option<(std::path::PathBuf, String)> {
+        self.embedded
+            .iter()
+            .find(|(embedded, _)| std::path::Path::new(embedded) == name)
+            .map(|(embedded, source)| (embedded.into(), source.clone()))
+    }
+
+    fn resolve_include(&self, name: &str) -> Result<(std::path::PathBuf, String), String> {
+        let tried: Vec<std::path::PathBuf> = self.roots.iter().map(|x| x.join(name)).collect();
+        if let Some(path) = tried.iter().find(|x| x.is_file()) {
+            return self.read(path);
+        }
+        self.find_embedded(std::path::Path::new(name))
+            .ok_or_else(|| {
+                AssetError::NotFound {
+                    kind: AssetKind::Shader,
+                    name: name.to_owned(),
+                    tried,
+                }
+                .into()
+            })
+    }
+
+    fn process_file(
+        &self,
+        path: std::path::PathBuf,
+        source: &str,
+        shader: &mut PreprocessedShader,
+        stack: &mut Vec<std::path::PathBuf>,
+    ) -> Result<(), String> {
+        if let Some(start) = stack.iter().position(|x| *x == path) {
+            let cycle: Vec<String> = stack[start..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|x| x.display().to_string())
+                .collect();
+            return Err(format!("Shader include cycle: {}", cycle.join(" -> ")));
+        }
+        let is_pragma_once = |line: &str| line.split_whitespace().eq(["#pragma", "once"]);
+        let index = match shader.files.iter().position(|x| *x == path) {
+            Some(_) if source.lines().any(is_pragma_once) => return Ok(()),
+            Some(index) => index,
+            None => {
+                shader.files.push(path.clone());
+                shader.texts.push(source.to_owned());
+                shader.files.len() - 1
+            }
+        };
+        let is_included = !stack.is_empty();
+        stack.push(path.clone());
+        // `#line` can't come before `#version`, the top file starts at line 1
+        // anyway
+        if is_included {
+            shader.source.push_str(&format!("#line 1 {}\n", index));
+        }
+        for (number, line) in source.lines().enumerate() {
+            let directive = line.trim_start();
+            let location = || format!("{}:{}", path.display(), number + 1);
+            if let Some(rest) = directive.strip_prefix("#include") {
+                let name = parse_include_name(rest)
+                    .ok_or_else(|| format!("{}: expected #include \"file\"", location()))?;
+                let (include, include_source) = self
+                    .resolve_include(name)
+                    .map_err(|e| format!("{}: {}", location(), e))?;
+                self.process_file(include, &include_source, shader, stack)?;
+                // `#line n` numbers the line after it
+                shader
+                    .source
+                    .push_str(&format!("#line {} {}\n", number + 2, index));
+            } else if directive.starts_with("#version") {
+                if is_included {
+                    return Err(format!("{}: included files can't set #version", location()));
+                }
+                shader.source.push_str(line);
+                shader.source.push('\n');
+                shader.source.push_str(&self.define_lines());
+                shader
+                    .source
+                    .push_str(&format!("#line {} {}\n", number + 2, index));
+            } else if is_pragma_once(line) {
+                // handled above, the empty line keeps the numbering
+                shader.source.push('\n');
+            } else {
+                shader.source.push_str(line);
+                shader.source.push('\n');
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+impl Default for ShaderPreprocessor {
+    fn default() -> Self {
+        ShaderPreprocessor::new()
+    }
+}
+
+// `rest` is what follows `#include`, e.g. ` "common/lighting.glsl"`
+fn parse_include_name(rest: &str) -> Option<&str> {
+    let rest = rest.trim_start().strip_prefix('"')?;
+    let (name, trailing) = rest.split_once('"')?;
+    let trailing = trailing.trim();
+    (!name.is_empty() && (trailing.is_empty() || trailing.starts_with("//"))).then_some(name)
+}
+
+// A uniform or vertex attribute the linked program uses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActiveVariable {
+    // as reported by GL, arrays end in `[0]`
+    pub name: String,
+    // -1 for uniforms inside a uniform block and built-in attributes
+    pub location: i32,
+    pub gl_type: u32,
+    // number of array elements, 1 for everything else
+    pub size: i32,
+}
+
+impl ActiveVariable {
+    pub fn type_name(&self) -> &'static str {
+        glsl_type_name(self.gl_type)
+    }
+}
+
+impl std::fmt::Display for ActiveVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.type_name(), self.name)?;
+        if self.size > 1 {
+            write!(f, " ({} elements)", self.size)?;
+        }
+        write!(f, " at location {}", self.location)
+    }
+}
+
+pub fn glsl_type_name(gl_type: u32) -> &'static str {
+    match gl_type {
+        gl::FLOAT => "float",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::DOUBLE => "double",
+        gl::INT => "int",
+        gl::INT_VEC2 => "ivec2",
+        gl::INT_VEC3 => "ivec3",
+        gl::INT_VEC4 => "ivec4",
+        gl::UNSIGNED_INT => "uint",
+        gl::UNSIGNED_INT_VEC2 => "uvec2",
+        gl::UNSIGNED_INT_VEC3 => "uvec3",
+        gl::UNSIGNED_INT_VEC4 => "uvec4",
+        gl::BOOL => "bool",
+        gl::BOOL_VEC2 => "bvec2",
+        gl::BOOL_VEC3 => "bvec3",
+        gl::BOOL_VEC4 => "bvec4",
+        gl::FLOAT_MAT2 => "mat2",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl::FLOAT_MAT2x3 => "mat2x3",
+        gl::FLOAT_MAT2x4 => "mat2x4",
+        gl::FLOAT_MAT3x2 => "mat3x2",
+        gl::FLOAT_MAT3x4 => "mat3x4",
+        gl::FLOAT_MAT4x2 => "mat4x2",
+        gl::FLOAT_MAT4x3 => "mat4x3",
+        gl::SAMPLER_1D => "sampler1D",
+        gl::SAMPLER_2D => "sampler2D",
+        gl::SAMPLER_3D => "sampler3D",
+        gl::SAMPLER_CUBE => "samplerCube",
+        gl::SAMPLER_1D_SHADOW => "sampler1DShadow",
+        gl::SAMPLER_2D_SHADOW => "sampler2DShadow",
+        gl::SAMPLER_CUBE_SHADOW => "samplerCubeShadow",
+        gl::SAMPLER_2D_ARRAY => "sampler2DArray",
+        gl::SAMPLER_2D_ARRAY_SHADOW => "sampler2DArrayShadow",
+        gl::SAMPLER_2D_MULTISAMPLE => "sampler2DMS",
+        gl::SAMPLER_BUFFER => "samplerBuffer",
+        gl::INT_SAMPLER_2D => "isampler2D",
+        gl::UNSIGNED_INT_SAMPLER_2D => "usampler2D",
+        _ => "unknown",
+    }
+}
+
+type GetActiveFn = unsafe fn(u32, u32, i32, *mut i32, *mut i32, *mut u32, *mut i8);
+
+// Lists the active uniforms or attributes, `location` is left at -1
+unsafe fn query_active_variables(
+    program: u32,
+    count_enum: u32,
+    max_length_enum: u32,
+    get_active: GetActiveFn,
+) -> Vec<ActiveVariable> {
+    let mut count = 0;
+    let mut max_length = 0;
+    gl::GetProgramiv(program, count_enum, &mut count);
+    gl::GetProgramiv(program, max_length_enum, &mut max_length);
+
+    let mut variables = Vec::with_capacity(count as usize);
+    let mut name = vec![0u8; max_length.max(1) as usize];
+    for index in 0..count as u32 {
+        let mut length = 0;
+        let mut size = 0;
+        let mut gl_type = 0;
+        get_active(
+            program,
+            index,
+            name.len() as i32,
+            &mut length,
+            &mut size,
+            &mut gl_type,
+            name.as_mut_ptr() as *mut i8,
+        );
+        variables.push(ActiveVariable {
+            name: String::from_utf8_lossy(&name[..length as usize]).into_owned(),
+            location: -1,
+            gl_type,
+            size,
+        });
+    }
+    variables
+}
+
+pub struct ShaderProgram {
+    pub id: u32,
+    uniforms: Vec<ActiveVariable>,
+    attributes: Vec<ActiveVariable>,
+    // every accepted uniform name, array elements and the name without
+    // `[0]` included, to its index in `uniforms`, array element and location
+    uniform_locations: std::collections::HashMap<String, (usize, i32, i32)>,
+}
+
+impl ShaderProgram {
+    pub unsafe fn new() -> Self {
+        let id = gl::CreateProgram();
+        ShaderProgram {
+            id,
+            uniforms: Vec::new(),
+            attributes: Vec::new(),
+            uniform_locations: std::collections::HashMap::new(),
+        }
+    }
+
+    pub unsafe fn attach_shader(&self, shader: Shader) -> () {
+        gl::AttachShader(self.id, shader.id);
+    }
+
+    pub unsafe fn link_program(&mut self) -> Result<(), String> {
+        gl::LinkProgram(self.id);
+        check_shader_link_errors(self.id)?;
+        self.reflect();
+        Ok(())
+    }
+
+    // Looks up every active uniform and attribute once, so setting uniforms
+    // doesn't have to ask the driver
+    unsafe fn reflect(&mut self) {
+        self.uniforms = query_active_variables(
+            self.id,
+            gl::ACTIVE_UNIFORMS,
+            gl::ACTIVE_UNIFORM_MAX_LENGTH,
+            gl::GetActiveUniform,
+        );
+        self.uniform_locations.clear();
+        for (idx, uniform) in self.uniforms.iter_mut().enumerate() {
+            uniform.location = uniform_location(self.id, &uniform.name);
+            self.uniform_locations
+                .insert(uniform.name.clone(), (idx, 0, uniform.location));
+            if let Some(base) = uniform.name.strip_suffix("[0]") {
+                self.uniform_locations
+                    .insert(base.to_owned(), (idx, 0, uniform.location));
+                for element in 1..uniform.size {
+                    let name = format!("{}[{}]", base, element);
+                    let location = uniform_location(self.id, &name);
+                    self.uniform_locations
+                        .insert(name, (idx, element, location));
+                }
+            }
+        }
+
+        self.attributes = query_active_variables(
+            self.id,
+            gl::ACTIVE_ATTRIBUTES,
+            gl::ACTIVE_ATTRIBUTE_MAX_LENGTH,
+            gl::GetActiveAttrib,
+        );
+        for attribute in &mut self.attributes {
+            let name = std::ffi::CString::new(attribute.name.as_str()).unwrap();
+            attribute.location = gl::GetAttribLocation(self.id, name.as_ptr());
+        }
+    }
+
+    // Active uniforms in the order GL reports them, each array once
+    pub fn uniforms(&self) -> &[ActiveVariable] {
+        &self.uniforms
+    }
+
+    // Accepts array elements like `lights[2].diffuse` too
+    pub fn uniform(&self, name: &str) -> Option<&ActiveVariable> {
+        self.uniform_locations
+            .get(name)
+            .map(|(idx, _, _)| &self.uniforms[*idx])
+    }
+
+    pub fn attributes(&self) -> &[ActiveVariable] {
+        &self.attributes
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&ActiveVariable> {
+        self.attributes.iter().find(|x| x.name == name)
+    }
+
+    pub unsafe fn use_program(&self) {
+        gl::UseProgram(self.id);
+    }
+
+    // Checks `value` against the GLSL type first. Slices and arrays start at
+    // `name`, which can be an element like `weights[2]`.
+    pub unsafe fn set_uniform<T: Uniform + ?Sized>(
+        &self,
+        name: &str,
+        value: &T,
+    ) -> Result<(), String> {
+        let (uniform, element, location) = match self.uniform_locations.get(name) {
+            Some((idx, element, location)) if *location != -1 => {
+                (&self.uniforms[*idx], *element, *location)
+            }
+            _ => return Err(format!("Failed to find location of uniform {}", name)),
+        };
+        if !value.accepts(uniform.gl_type) {
+            return Err(format!(
+                "Uniform {} is a {} but was set to a {}",
+                name,
+                uniform.type_name(),
+                value.type_name()
+            ));
+        }
+        let available = (uniform.size - element) as usize;
+        if value.len() > available {
+            return Err(format!(
+                "Uniform {} has room for {} elements but was set to {}",
+                name,
+                available,
+                value.len()
+            ));
+        }
+        self.use_program();
+        value.upload(location, uniform.gl_type);
+        check_gl_error()
+    }
+
+    // Writes every field of a GLSL struct, `name` can be an array element
+    // like `lights[3]`. A slice of structs starts at `name[0]`.
+    pub unsafe fn set_struct<T: UniformStruct + ?Sized>(
+        &self,
+        name: &str,
+        value: &T,
+    ) -> Result<(), String> {
+        let prefix = format!("{}.", name);
+        let element_prefix = format!("{}[", name);
+        if !self
+            .uniform_locations
+            .keys()
+            .any(|x| x.starts_with(&prefix) || x.starts_with(&element_prefix))
+        {
+            return Err(format!("Failed to find uniform struct {}", name));
+        }
+        value.set_fields(self, name)
+    }
+
+    // For `UniformStruct` impls. Fields the shader optimized out are skipped,
+    // wrong types are still an error.
+    pub unsafe fn set_field<T: Uniform + ?Sized>(
+        &self,
+        prefix: &str,
+        field: &str,
+        value: &T,
+    ) -> Result<(), String> {
+        let name = format!("{}.{}", prefix, field);
+        if self.uniform(&name).is_none() {
+            return Ok(());
+        }
+        self.set_uniform(&name, value)
+    }
+
+    pub unsafe fn set_uniform_i32(&self, name: &str, value: i32) -> Result<(), String> {
+        self.set_uniform(name, &value)
+    }
+
+    pub unsafe fn set_uniform_f32(&self, name: &str, value: f32) -> Result<(), String> {
+        self.set_uniform(name, &value)
+    }
+
+    pub unsafe fn set_uniform_mat4(&self, name: &str, value: &glam::Mat4) -> Result<(), String> {
+        self.set_uniform(name, value)
+    }
+
+    pub unsafe fn set_uniform_vec3(
+        &self,
+        name: &str,
+        v1: f32,
+        v2: f32,
+        v3: f32,
+    ) -> Result<(), String> {
+        self.set_uniform(name, &glam::vec3(v1, v2, v3))
+    }
+
+    // Points the uniform block `name` at `buffer`'s binding point. Fails if
+    // the block doesn't exist or its size doesn't match `T`.
+    pub unsafe fn bind_uniform_block<T: Std140>(
+        &self,
+        name: &str,
+        buffer: &UniformBuffer<T>,
+    ) -> Result<(), String> {
+        self.bind_uniform_block_to(
+            name,
+            buffer.binding(),
+            std::mem::size_of::<T>(),
+            std::any::type_name::<T>(),
+        )
+    }
+
+    // `bind_uniform_block` for a binding point whose buffer is not at hand,
+    // `size` and `type_name` describe its contents
+    unsafe fn bind_uniform_block_to(
+        &self,
+        name: &str,
+        binding: u32,
+        size: usize,
+        type_name: &str,
+    ) -> Result<(), String> {
+        let c_name = std::ffi::CString::new(name).map_err(|e| e.to_string())?;
+        let index = gl::GetUniformBlockIndex(self.id, c_name.as_ptr());
+        if index == gl::INVALID_INDEX {
+            return Err(format!("Failed to find uniform block {}", name));
+        }
+        let mut block_size = 0;
+        gl::GetActiveUniformBlockiv(self.id, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut block_size);
+        if block_size as usize != size {
+            return Err(format!(
+                "Uniform block {} is {} bytes but {} is {} bytes",
+                name, block_size, type_name, size
+            ));
+        }
+        gl::UniformBlockBinding(self.id, index, binding);
+        check_gl_error()
+    }
+
+    // Copies the current value of every uniform that `self` and `target`
+    // both have with the same type, and the uniform block bindings, e.g.
+    // after relinking a changed shader
+    unsafe fn copy_uniforms_to(&self, target: &ShaderProgram) {
+        let mut block_count = 0;
+        gl::GetProgramiv(self.id, gl::ACTIVE_UNIFORM_BLOCKS, &mut block_count);
+        for index in 0..block_count as u32 {
+            let mut name = [0u8; 256];
+            let mut length = 0;
+            gl::GetActiveUniformBlockName(
+                self.id,
+                index,
+                name.len() as i32,
+                &mut length,
+                name.as_mut_ptr() as *mut i8,
+            );
+            let mut binding = 0;
+            gl::GetActiveUniformBlockiv(self.id, index, gl::UNIFORM_BLOCK_BINDING, &mut binding);
+            let name = std::ffi::CString::new(&name[..length as usize]).unwrap();
+            let target_index = gl::GetUniformBlockIndex(target.id, name.as_ptr());
+            if target_index != gl::INVALID_INDEX {
+                gl::UniformBlockBinding(target.id, target_index, binding as u32);
+            }
+        }
+
+        target.use_program();
+        for (name, (idx, _, source)) in &self.uniform_locations {
+            let uniform = &self.uniforms[*idx];
+            let Some(components) = UniformComponents::from_gl_type(uniform.gl_type) else {
+                continue;
+            };
+            let Some((target_idx, _, destination)) = target.uniform_locations.get(name) else {
+                continue;
+            };
+            // the array base names alias their first element
+            let is_alias = uniform.name != *name && !name.ends_with(']');
+            if *source == -1
+                || *destination == -1
+                || is_alias
+                || target.uniforms[*target_idx].gl_type != uniform.gl_type
+            {
+                continue;
+            }
+            components.copy(self.id, *source, *destination);
+        }
+    }
+}
+
+unsafe fn uniform_location(program: u32, name: &str) -> i32 {
+    let name = std::ffi::CString::new(name).unwrap();
+    gl::GetUniformLocation(program, name.as_ptr())
+}
+
+impl Drop for ShaderProgram {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) }
+    }
+}
+
+// A value `ShaderProgram::set_uniform` can write, one element or an array
+pub trait Uniform {
+    // Whether a GLSL variable of `gl_type` can hold this
+    fn accepts(&self, gl_type: u32) -> bool;
+    // For mismatch errors, e.g. `vec3` or `float[4]`
+    fn type_name(&self) -> String;
+    // Number of array elements written
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    unsafe fn upload(&self, location: i32, gl_type: u32);
+}
+
+// Single uniform values, every one of these also works as a slice or array
+pub trait UniformElement: Sized {
+    const TYPE_NAME: &'static str;
+    fn accepts(gl_type: u32) -> bool;
+    unsafe fn upload_slice(location: i32, gl_type: u32, values: &[Self]);
+}
+
+impl<T: UniformElement> Uniform for T {
+    fn accepts(&self, gl_type: u32) -> bool {
+        T::accepts(gl_type)
+    }
+
+    fn type_name(&self) -> String {
+        T::TYPE_NAME.to_owned()
+    }
+
+    fn len(&self) -> usize {
+        1
+    }
+
+    unsafe fn upload(&self, location: i32, gl_type: u32) {
+        T::upload_slice(location, gl_type, std::slice::from_ref(self))
+    }
+}
+
+impl<T: UniformElement> Uniform for [T] {
+    fn accepts(&self, gl_type: u32) -> bool {
+        T::accepts(gl_type)
+    }
+
+    fn type_name(&self) -> String {
+        format!("{}[{}]", T::TYPE_NAME, self.len())
+    }
+
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    unsafe fn upload(&self, location: i32, gl_type: u32) {
+        T::upload_slice(location, gl_type, self)
+    }
+}
+
+impl<T: UniformElement, const N: usize> Uniform for [T; N] {
+    fn accepts(&self, gl_type: u32) -> bool {
+        T::accepts(gl_type)
+    }
+
+    fn type_name(&self) -> String {
+        format!("{}[{}]", T::TYPE_NAME, N)
+    }
+
+    fn len(&self) -> usize {
+        N
+    }
+
+    unsafe fn upload(&self, location: i32, gl_type: u32) {
+        T::upload_slice(location, gl_type, self)
+    }
+}
+
+// The glam types are uploaded straight from memory
+const _: () = assert!(std::mem::size_of::<glam::Vec2>() == 8);
+const _: () = assert!(std::mem::size_of::<glam::Vec3>() == 12);
+const _: () = assert!(std::mem::size_of::<glam::Vec4>() == 16);
+const _: () = assert!(std::mem::size_of::<glam::Mat2>() == 16);
+const _: () = assert!(std::mem::size_of::<glam::Mat3>() == 36);
+const _: () = assert!(std::mem::size_of::<glam::Mat4>() == 64);
+
+macro_rules! impl_uniform_element {
+    ($type:ty, $name:literal, [$($gl_type:path),+], |$location:ident, $count:ident, $ptr:ident| $upload:expr) => {
+        impl UniformElement for $type {
+            const TYPE_NAME: &'static str = $name;
+
+            fn accepts(gl_type: u32) -> bool {
+                matches!(gl_type, $($gl_type)|+)
+            }
+
+            unsafe fn upload_slice($location: i32, _gl_type: u32, values: &[Self]) {
+                let $count = values.len() as i32;
+                let $ptr = values.as_ptr() as *const _;
+                $upload;
+            }
+        }
+    };
+}
+
+impl_uniform_element!(f32, "float", [gl::FLOAT], |l, n, p| gl::Uniform1fv(l, n, p));
+impl_uniform_element!(u32, "uint", [gl::UNSIGNED_INT], |l, n, p| gl::Uniform1uiv(
+    l, n, p
+));
+impl_uniform_element!(glam::Vec2, "vec2", [gl::FLOAT_VEC2], |l, n, p| {
+    gl::Uniform2fv(l, n, p)
+});
+impl_uniform_element!(glam::Vec3, "vec3", [gl::FLOAT_VEC3], |l, n, p| {
+    gl::Uniform3fv(l, n, p)
+});
+impl_uniform_element!(glam::Vec4, "vec4", [gl::FLOAT_VEC4], |l, n, p| {
+    gl::Uniform4fv(l, n, p)
+});
+impl_uniform_element!(glam::IVec2, "ivec2", [gl::INT_VEC2], |l, n, p| {
+    gl::Uniform2iv(l, n, p)
+});
+impl_uniform_element!(glam::IVec3, "ivec3", [gl::INT_VEC3], |l, n, p| {
+    gl::Uniform3iv(l, n, p)
+});
+impl_uniform_element!(glam::IVec4, "ivec4", [gl::INT_VEC4], |l, n, p| {
+    gl::Uniform4iv(l, n, p)
+});
+impl_uniform_element!(glam::UVec2, "uvec2", [gl::UNSIGNED_INT_VEC2], |l, n, p| {
+    gl::Uniform2uiv(l, n, p)
+});
+impl_uniform_element!(glam::UVec3, "uvec3", [gl::UNSIGNED_INT_VEC3], |l, n, p| {
+    gl::Uniform3uiv(l, n, p)
+});
+impl_uniform_element!(glam::UVec4, "uvec4", [gl::UNSIGNED_INT_VEC4], |l, n, p| {
+    gl::Uniform4uiv(l, n, p)
+});
+impl_uniform_element!(glam::Mat2, "mat2", [gl::FLOAT_MAT2], |l, n, p| {
+    gl::UniformMatrix2fv(l, n, gl::FALSE, p)
+});
+impl_uniform_element!(glam::Mat3, "mat3", [gl::FLOAT_MAT3], |l, n, p| {
+    gl::UniformMatrix3fv(l, n, gl::FALSE, p)
+});
+impl_uniform_element!(glam::Mat4, "mat4", [gl::FLOAT_MAT4], |l, n, p| {
+    gl::UniformMatrix4fv(l, n, gl::FALSE, p)
+});
+
+// ints also select the texture unit of samplers
+impl UniformElement for i32 {
+    const TYPE_NAME: &'static str = "int";
+
+    fn accepts(gl_type: u32) -> bool {
+        gl_type == gl::INT || glsl_type_name(gl_type).contains("sampler")
+    }
+
+    unsafe fn upload_slice(location: i32, _gl_type: u32, values: &[Self]) {
+        gl::Uniform1iv(location, values.len() as i32, values.as_ptr());
+    }
+}
+
+impl UniformElement for bool {
+    const TYPE_NAME: &'static str = "bool";
+
+    fn accepts(gl_type: u32) -> bool {
+        gl_type == gl::BOOL
+    }
+
+    unsafe fn upload_slice(location: i32, _gl_type: u32, values: &[Self]) {
+        let values: Vec<i32> = values.iter().map(|x| *x as i32).collect();
+        gl::Uniform1iv(location, values.len() as i32, values.as_ptr());
+    }
+}
+
+// Rotations go to a mat3 or mat4
+impl UniformElement for glam::Quat {
+    const TYPE_NAME: &'static str = "quat";
+
+    fn accepts(gl_type: u32) -> bool {
+        gl_type == gl::FLOAT_MAT3 || gl_type == gl::FLOAT_MAT4
+    }
+
+    unsafe fn upload_slice(location: i32, gl_type: u32, values: &[Self]) {
+        if gl_type == gl::FLOAT_MAT3 {
+            let matrices: Vec<glam::Mat3> =
+                values.iter().map(|x| glam::Mat3::from_quat(*x)).collect();
+            glam::Mat3::upload_slice(location, gl_type, &matrices);
+        } else {
+            let matrices: Vec<glam::Mat4> =
+                values.iter().map(|x| glam::Mat4::from_quat(*x)).collect();
+            glam::Mat4::upload_slice(location, gl_type, &matrices);
+        }
+    }
+}
+
+// How a uniform's value is read and written
+#[derive(Clone, Copy)]
+enum UniformComponents {
+    Float(i32),
+    Int(i32),
+    Uint(i32),
+    Matrix(i32),
+}
+
+impl UniformComponents {
+    fn from_gl_type(gl_type: u32) -> Option<Self> {
+        Some(match gl_type {
+            gl::FLOAT => UniformComponents::Float(1),
+            gl::FLOAT_VEC2 => UniformComponents::Float(2),
+            gl::FLOAT_VEC3 => UniformComponents::Float(3),
+            gl::FLOAT_VEC4 => UniformComponents::Float(4),
+            gl::INT | gl::BOOL => UniformComponents::Int(1),
+            gl::INT_VEC2 | gl::BOOL_VEC2 => UniformComponents::Int(2),
+            gl::INT_VEC3 | gl::BOOL_VEC3 => UniformComponents::Int(3),
+            gl::INT_VEC4 | gl::BOOL_VEC4 => UniformComponents::Int(4),
+            gl::UNSIGNED_INT => UniformComponents::Uint(1),
+            gl::UNSIGNED_INT_VEC2 => UniformComponents::Uint(2),
+            gl::UNSIGNED_INT_VEC3 => UniformComponents::Uint(3),
+            gl::UNSIGNED_INT_VEC4 => UniformComponents::Uint(4),
+            gl::FLOAT_MAT2 => UniformComponents::Matrix(2),
+            gl::FLOAT_MAT3 => UniformComponents::Matrix(3),
+            gl::FLOAT_MAT4 => UniformComponents::Matrix(4),
+            // samplers hold the texture unit
+            gl::SAMPLER_1D
+            | gl::SAMPLER_2D
+            | gl::SAMPLER_3D
+            | gl::SAMPLER_CUBE
+            | gl::SAMPLER_1D_SHADOW
+            | gl::SAMPLER_2D_SHADOW
+            | gl::SAMPLER_CUBE_SHADOW
+            | gl::SAMPLER_2D_ARRAY
+            | gl::SAMPLER_2D_ARRAY_SHADOW
+            | gl::SAMPLER_2D_MULTISAMPLE
+            | gl::SAMPLER_BUFFER
+            | gl::INT_SAMPLER_2D
+            | gl::UNSIGNED_INT_SAMPLER_2D => UniformComponents::Int(1),
+            _ => return None,
+        })
+    }
+
+    // Reads the value at `source` in `program` and writes it to `destination`
+    // in the program in use
+    unsafe fn copy(self, program: u32, source: i32, destination: i32) {
+        let mut floats = [0.0f32; 16];
+        let mut ints = [0i32; 4];
+        let mut uints = [0u32; 4];
+        match self {
+            UniformComponents::Float(count) => {
+                gl::GetUniformfv(program, source, floats.as_mut_ptr());
+                match count {
+                    1 => gl::Uniform1fv(destination, 1, floats.as_ptr()),
+                    2 => gl::Uniform2fv(destination, 1, floats.as_ptr()),
+                    3 => gl::Uniform3fv(destination, 1, floats.as_ptr()),
+                    _ => gl::Uniform4fv(destination, 1, floats.as_ptr()),
+                }
+            }
+            UniformComponents::Int(count) => {
+                gl::GetUniformiv(program, source, ints.as_mut_ptr());
+                match count {
+                    1 => gl::Uniform1iv(destination, 1, ints.as_ptr()),
+                    2 => gl::Uniform2iv(destination, 1, ints.as_ptr()),
+                    3 => gl::Uniform3iv(destination, 1, ints.as_ptr()),
+                    _ => gl::Uniform4iv(destination, 1, ints.as_ptr()),
+                }
+            }
+            UniformComponents::Uint(count) => {
+                gl::GetUniformuiv(program, source, uints.as_mut_ptr());
+                match count {
+                    1 => gl::Uniform1uiv(destination, 1, uints.as_ptr()),
+                    2 => gl::Uniform2uiv(destination, 1, uints.as_ptr()),
+                    3 => gl::Uniform3uiv(destination, 1, uints.as_ptr()),
+                    _ => gl::Uniform4uiv(destination, 1, uints.as_ptr()),
+                }
+            }
+            UniformComponents::Matrix(size) => {
+                gl::GetUniformfv(program, source, floats.as_mut_ptr());
+                match size {
+                    2 => gl::UniformMatrix2fv(destination, 1, gl::FALSE, floats.as_ptr()),
+                    3 => gl::UniformMatrix3fv(destination, 1, gl::FALSE, floats.as_ptr()),
+                    _ => gl::UniformMatrix4fv(destination, 1, gl::FALSE, floats.as_ptr()),
+                }
+            }
+        }
+    }
+}
+
+// A Rust type mirroring a GLSL struct, see `ShaderProgram::set_struct`
+pub trait UniformStruct {
+    // Sets every field as `<prefix>.<glslName>` with `ShaderProgram::set_field`
+    unsafe fn set_fields(&self, program: &ShaderProgram, prefix: &str) -> Result<(), String>;
+}
+
+impl<T: UniformStruct> UniformStruct for [T] {
+    unsafe fn set_fields(&self, program: &ShaderProgram, prefix: &str) -> Result<(), String> {
+        for (idx, value) in self.iter().enumerate() {
+            value.set_fields(program, &format!("{}[{}]", prefix, idx))?;
+        }
+        Ok(())
+    }
+}
+
+/// Alignment and size of a type in a std140 uniform block
+///
+/// # Safety
+/// `ALIGN` and `SIZE` have to match the std140 rules and the Rust type has
+/// to have the same memory layout as the GLSL type.
+pub unsafe trait Std140Field {
+    const ALIGN: usize;
+    const SIZE: usize;
+}
+
+macro_rules! impl_std140_field {
+    ($($type:ty => $align:literal, $size:literal;)+) => {
+        $(unsafe impl Std140Field for $type {
+            const ALIGN: usize = $align;
+            const SIZE: usize = $size;
+        })+
+    };
+}
+
+// bools are 4 bytes in GLSL, use u32. mat3 columns are padded to vec4 so
+// glam's Mat3 doesn't fit, use a Mat4 or [Vec4; 3].
+impl_std140_field! {
+    f32 => 4, 4;
+    i32 => 4, 4;
+    u32 => 4, 4;
+    glam::Vec2 => 8, 8;
+    glam::IVec2 => 8, 8;
+    glam::UVec2 => 8, 8;
+    glam::Vec3 => 16, 12;
+    glam::IVec3 => 16, 12;
+    glam::UVec3 => 16, 12;
+    glam::Vec4 => 16, 16;
+    glam::IVec4 => 16, 16;
+    glam::UVec4 => 16, 16;
+    glam::Mat4 => 16, 64;
+}
+
+// Array elements are padded to 16 bytes, only 16 byte multiples fit as is
+unsafe impl<const N: usize> Std140Field for [glam::Vec4; N] {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 16 * N;
+}
+
+// Vec3A is padded to 16 bytes like a vec3 array element
+unsafe impl<const N: usize> Std140Field for [glam::Vec3A; N] {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 16 * N;
+}
+
+unsafe impl<const N: usize> Std140Field for [glam::Mat4; N] {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 64 * N;
+}
+
+/// A `#[repr(C)]` struct that matches a std140 uniform block byte for byte,
+/// implement it with `std140_block!`
+///
+/// # Safety
+/// Every field has to sit at its std140 offset.
+pub unsafe trait Std140: Copy {}
+
+// Implements `Std140` after checking at compile time that every field is at
+// the offset std140 puts it. Fields have to be listed in declaration order,
+// add explicit padding fields where the check fails, e.g. between two vec3s.
+#[macro_export]
+macro_rules! std140_block {
+    ($type:ty { $($field:ident: $field_type:ty),+ $(,)? }) => {
+        const _: () = {
+            let mut offset = 0usize;
+            $(
+                let align = <$field_type as $crate::Std140Field>::ALIGN;
+                offset = offset.div_ceil(align) * align;
+                assert!(
+                    std::mem::offset_of!($type, $field) == offset,
+                    concat!("std140 offset mismatch for ", stringify!($type), "::", stringify!($field))
+                );
+                offset += <$field_type as $crate::Std140Field>::SIZE;
+            )+
+            assert!(offset <= std::mem::size_of::<$type>());
+        };
+        unsafe impl $crate::Std140 for $type {}
+    };
+}
+
+thread_local! {
+    // bit per uniform buffer binding point in use
+    static UNIFORM_BINDINGS: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+// GPU copy of a std140 block. Each buffer takes the lowest free binding
+// point until it is dropped, programs are pointed at it with
+// `ShaderProgram::bind_uniform_block`.
+pub struct UniformBuffer<T: Std140> {
+    pub id: u32,
+    binding: u32,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<T: Std140> UniformBuffer<T> {
+    pub unsafe fn new(value: &T) -> Result<Self, String> {
+        let mut max_bindings = 0;
+        gl::GetIntegerv(gl::MAX_UNIFORM_BUFFER_BINDINGS, &mut max_bindings);
+        let max_bindings = (max_bindings as u32).min(u64::BITS);
+        let used = UNIFORM_BINDINGS.with(|x| x.get());
+        let binding = used.trailing_ones();
+        if binding >= max_bindings {
+            return Err(format!(
+                "All {} uniform buffer binding points are in use",
+                max_bindings
+            ));
+        }
+
+        let mut id = 0;
+        gl::GenBuffers(1, &mut id);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, id);
+        gl::BufferData(
+            gl::UNIFORM_BUFFER,
+            std::mem::size_of::<T>() as isize,
+            value as *const T as *const c_void,
+            gl::DYNAMIC_DRAW,
+        );
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, id);
+        UNIFORM_BINDINGS.with(|x| x.set(used | 1 << binding));
+
+        let buffer = UniformBuffer {
+            id,
+            binding,
+            marker: std::marker::PhantomData,
+        };
+        check_gl_error()?;
+        Ok(buffer)
+    }
+
+    pub unsafe fn update(&self, value: &T) {
+        gl::BindBuffer(gl::UNIFORM_BUFFER, self.id);
+        gl::BufferSubData(
+            gl::UNIFORM_BUFFER,
+            0,
+            std::mem::size_of::<T>() as isize,
+            value as *const T as *const c_void,
+        );
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+    }
+
+    pub fn binding(&self) -> u32 {
+        self.binding
+    }
+}
+
+impl<T: Std140> Drop for UniformBuffer<T> {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteBuffers(1, &self.id) };
+        UNIFORM_BINDINGS.with(|x| x.set(x.get() & !(1 << self.binding)));
+    }
+}
+
+// Per frame camera data shared by every program through the `Camera` block
+// in `common/camera.glsl`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CameraBlock {
+    pub view: glam::Mat4,
+    pub projection: glam::Mat4,
+}
+
+std140_block!(CameraBlock {
+    view: glam::Mat4,
+    projection: glam::Mat4,
+});
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageAccess {
+    ReadOnly = gl::READ_ONLY,
+    WriteOnly = gl::WRITE_ONLY,
+    ReadWrite = gl::READ_WRITE,
+}
+
+// Which later reads have to see the writes of a compute dispatch, combine
+// with `|`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryBarrier(u32);
+
+impl MemoryBarrier {
+    pub const VERTEX_ATTRIB_ARRAY: Self = MemoryBarrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+    pub const ELEMENT_ARRAY: Self = MemoryBarrier(gl::ELEMENT_ARRAY_BARRIER_BIT);
+    pub const UNIFORM: Self = MemoryBarrier(gl::UNIFORM_BARRIER_BIT);
+    pub const TEXTURE_FETCH: Self = MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+    pub const SHADER_IMAGE_ACCESS: Self = MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    pub const COMMAND: Self = MemoryBarrier(gl::COMMAND_BARRIER_BIT);
+    pub const PIXEL_BUFFER: Self = MemoryBarrier(gl::PIXEL_BUFFER_BARRIER_BIT);
+    pub const TEXTURE_UPDATE: Self = MemoryBarrier(gl::TEXTURE_UPDATE_BARRIER_BIT);
+    pub const BUFFER_UPDATE: Self = MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+    pub const FRAMEBUFFER: Self = MemoryBarrier(gl::FRAMEBUFFER_BARRIER_BIT);
+    pub const SHADER_STORAGE: Self = MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+    pub const ALL: Self = MemoryBarrier(gl::ALL_BARRIER_BITS);
+
+    pub unsafe fn wait(self) {
+        gl::MemoryBarrier(self.0);
+    }
+}
+
+impl std::ops::BitOr for MemoryBarrier {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        MemoryBarrier(self.0 | other.0)
+    }
+}
+
+// Program with a single compute stage, needs GL 4.3
+pub struct ComputeProgram {
+    program: ShaderProgram,
+    work_group_size: [u32; 3],
+}
+
+impl ComputeProgram {
+    pub unsafe fn new(shader: Shader) -> Result<Self, String> {
+        let mut shader_type = 0;
+        gl::GetShaderiv(shader.id, gl::SHADER_TYPE, &mut shader_type);
+        if shader_type as u32 != gl::COMPUTE_SHADER {
+            return Err("ComputeProgram needs a compute shader".to_owned());
+        }
+        let mut program = ShaderProgram::new();
+        program.attach_shader(shader);
+        program.link_program()?;
+
+        let mut size = [0i32; 3];
+        gl::GetProgramiv(program.id, gl::COMPUTE_WORK_GROUP_SIZE, size.as_mut_ptr());
+        Ok(ComputeProgram {
+            program,
+            work_group_size: size.map(|x| x as u32),
+        })
+    }
+
+    pub unsafe fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self, String> {
+        ComputeProgram::new(Shader::from_file(path, ShaderType::ComputeShader)?)
+    }
+
+    // `local_size_x/y/z` from the shader
+    pub fn work_group_size(&self) -> [u32; 3] {
+        self.work_group_size
+    }
+
+    // Runs `x * y * z` work groups. The writes are only visible to later
+    // commands after a `MemoryBarrier` covering how they are read.
+    pub unsafe fn dispatch(&self, x: u32, y: u32, z: u32) -> Result<(), String> {
+        for (axis, count) in [x, y, z].into_iter().enumerate() {
+            let mut max = 0;
+            gl::GetIntegeri_v(gl::MAX_COMPUTE_WORK_GROUP_COUNT, axis as u32, &mut max);
+            if count > max as u32 {
+                return Err(format!(
+                    "{} work groups on axis {} but the driver allows {}",
+                    count, axis, max
+                ));
+            }
+        }
+        self.program.use_program();
+        gl::DispatchCompute(x, y, z);
+        check_gl_error()
+    }
+
+    // Enough work groups to cover `width * height * depth` invocations
+    pub unsafe fn dispatch_size(&self, width: u32, height: u32, depth: u32) -> Result<(), String> {
+        let [x, y, z] = self.work_group_size;
+        self.dispatch(width.div_ceil(x), height.div_ceil(y), depth.div_ceil(z))
+    }
+}
+
+impl std::ops::Deref for ComputeProgram {
+    type Target = ShaderProgram;
+
+    fn deref(&self) -> &ShaderProgram {
+        &self.program
+    }
+}
+
+// Vertices per patch for tessellation shaders
+pub unsafe fn set_patch_vertices(count: u32) -> Result<(), String> {
+    ShaderType::TessControlShader.check_supported()?;
+    gl::PatchParameteri(gl::PATCH_VERTICES, count as i32);
+    check_gl_error()
+}
+
+pub const SHADER_CACHE_ENV_VAR: &str = "LEARNOPENGL_SHADER_CACHE";
+
+// Start of every cache file, bump the number when the layout changes
+const PROGRAM_BINARY_MAGIC: &[u8; 8] = b"LOGLPB1\0";
+const PROGRAM_BINARY_EXTENSION: &str = "bin";
+// Entries used least recently are deleted above this total
+pub const DEFAULT_SHADER_CACHE_MAX_BYTES: u64 = 64 << 20;
+
+// Linked program binaries stored on disk, so later runs can skip compiling
+// and linking. Entries are keyed by the preprocessed sources, defines
+// included, and the driver that produced them. A driver that rejects a blob,
+// e.g. after an update, just gets a full compile and a fresh entry. Stale
+// entries pile up as shaders change, so the oldest ones are pruned once the
+// cache is over its size limit.
+pub struct ProgramBinaryCache {
+    dir: std::path::PathBuf,
+    driver: String,
+    max_bytes: u64,
+}
+
+impl ProgramBinaryCache {
+    // Needs a current context, the driver strings are part of the key.
+    // Creates `dir` if needed.
+    pub unsafe fn new(dir: impl Into<std::path::PathBuf>) -> Result<Self, String> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            format!(
+                "Failed to create shader cache directory {}: {}",
+                dir.display(),
+                e
+            )
+        })?;
+        let driver = [gl::VENDOR, gl::RENDERER, gl::VERSION]
+            .iter()
+            .map(|name| {
+                let value = gl::GetString(*name);
+                if value.is_null() {
+                    String::new()
+                } else {
+                    std::ffi::CStr::from_ptr(value as *const _)
+                        .to_string_lossy()
+                        .into_owned()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(ProgramBinaryCache {
+            dir,
+            driver,
+            max_bytes: DEFAULT_SHADER_CACHE_MAX_BYTES,
+        })
+    }
+
+    // The cache in $LEARNOPENGL_SHADER_CACHE, if it is set
+    pub unsafe fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var_os(SHADER_CACHE_ENV_VAR).filter(|dir| !dir.is_empty()) {
+            Some(dir) => Ok(Some(ProgramBinaryCache::new(dir)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &std::path::Path {
+        &self.dir
+    }
+
+    // Deletes every entry, other files in the directory are left alone
+    pub fn clear(&self) -> Result<(), String> {
+        for (path, ..) in self.entries()? {
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("Failed to delete {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    // Whether the driver can hand out program binaries at all
+    pub unsafe fn is_supported() -> bool {
+        if gl_version() < (4, 1) && !has_gl_extension("GL_ARB_get_program_binary") {
+            return false;
+        }
+        let mut formats = 0;
+        gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut formats);
+        formats > 0
+    }
+
+    // Loads the program from the cache, or compiles and links it and stores
+    // the result. Compile and link errors are reported like without a cache,
+    // failing to write the cache is not an error.
+    pub unsafe fn link(
+        &self,
+        stages: &[(&PreprocessedShader, ShaderType)],
+    ) -> Result<ShaderProgram, String> {
+        if !ProgramBinaryCache::is_supported() {
+            return link_stages(stages, false);
+        }
+        // surface errors of earlier calls here, `load` treats the one
+        // `ProgramBinary` raises as a miss
+        check_gl_error()?;
+        let path = self.dir.join(format!(
+            "{:016x}.{}",
+            self.key(stages),
+            PROGRAM_BINARY_EXTENSION
+        ));
+        if let Some(program) = self.load(&path) {
+            // pruning goes by modification time, mark the entry as used.
+            // Failing that only makes it an earlier candidate.
+            let _ = std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(std::time::SystemTime::now()));
+            return Ok(program);
+        }
+
+        let program = link_stages(stages, true)?;
+        // the program works either way, a read-only or full cache directory
+        // only means compiling again next run
+        if self.store(&path, &program).is_ok() {
+            let _ = self.prune();
+        }
+        Ok(program)
+    }
+
+    // Path, size and modification time of every entry
+    fn entries(&self) -> Result<Vec<(std::path::PathBuf, u64, std::time::SystemTime)>, String> {
+        let error = |e: std::io::Error| {
+            format!("Failed to list shader cache {}: {}", self.dir.display(), e)
+        };
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.dir).map_err(error)? {
+            let path = entry.map_err(error)?.path();
+            if path
+                .extension()
+                .is_some_and(|x| x == PROGRAM_BINARY_EXTENSION)
+            {
+                // another run may have deleted it in the meantime
+                if let Ok(metadata) = std::fs::metadata(&path) {
+                    let modified = metadata.modified().map_err(error)?;
+                    entries.push((path, metadata.len(), modified));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    // Deletes the least recently used entries until the cache fits in
+    // `max_bytes`
+    fn prune(&self) -> Result<(), String> {
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, size, _) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => total -= size,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => total -= size,
+                Err(e) => return Err(format!("Failed to delete {}: {}", path.display(), e)),
+            }
+        }
+        Ok(())
+    }
+
+    // FNV-1a, it has to give the same key in every run unlike `DefaultHasher`
+    fn key(&self, stages: &[(&PreprocessedShader, ShaderType)]) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes.iter().chain(&[0xff]) {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        write(self.driver.as_bytes());
+        for (shader, shader_type) in stages {
+            write(&(*shader_type as u32).to_le_bytes());
+            write(shader.source.as_bytes());
+        }
+        hash
+    }
+
+    unsafe fn load(&self, path: &std::path::Path) -> Option<ShaderProgram> {
+        let data = std::fs::read(path).ok()?;
+        let header = PROGRAM_BINARY_MAGIC.len() + 4;
+        if data.len() <= header || &data[..PROGRAM_BINARY_MAGIC.len()] != PROGRAM_BINARY_MAGIC {
+            let _ = std::fs::remove_file(path);
+            return None;
+        }
+        let format =
+            u32::from_le_bytes(data[PROGRAM_BINARY_MAGIC.len()..header].try_into().unwrap());
+        let binary = &data[header..];
+
+        let mut program = ShaderProgram::new();
+        gl::ProgramBinary(
+            program.id,
+            format,
+            binary.as_ptr() as *const c_void,
+            binary.len() as i32,
+        );
+        // a rejected binary sets INVALID_ENUM for an unknown format on top
+        // of the failed link status
+        gl::GetError();
+        let mut success = 0;
+        gl::GetProgramiv(program.id, gl::LINK_STATUS, &mut success);
+        if success == gl::FALSE as i32 {
+            let _ = std::fs::remove_file(path);
+            return None;
+        }
+        program.reflect();
+        Some(program)
+    }
+
+    unsafe fn store(&self, path: &std::path::Path, program: &ShaderProgram) -> Result<(), String> {
+        let mut length = 0;
+        gl::GetProgramiv(program.id, gl::PROGRAM_BINARY_LENGTH, &mut length);
+        if length <= 0 {
+            return Err("the driver returned an empty binary".to_owned());
+        }
+        let mut binary = vec![0u8; length as usize];
+        let mut format = 0;
+        gl::GetProgramBinary(
+            program.id,
+            length,
+            &mut length,
+            &mut format,
+            binary.as_mut_ptr() as *mut c_void,
+        );
+        check_gl_error()?;
+        binary.truncate(length as usize);
+
+        let mut data = Vec::with_capacity(PROGRAM_BINARY_MAGIC.len() + 4 + binary.len());
+        data.extend_from_slice(PROGRAM_BINARY_MAGIC);
+        data.extend_from_slice(&format.to_le_bytes());
+        data.extend_from_slice(&binary);
+        // written next to the entry first so a concurrent run never reads
+        // half a file
+        std::fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp_path, data).map_err(|e| e.to_string())?;
+        std::fs::rename(&tmp_path, path).map_err(|e| {
+            let _ = std::fs::remove_file(&tmp_path);
+            e.to_string()
+        })
+    }
+}
+
+unsafe fn link_stages(
+    stages: &[(&PreprocessedShader, ShaderType)],
+    retrievable: bool,
+) -> Result<ShaderProgram, String> {
+    let mut program = ShaderProgram::new();
+    for (shader, shader_type) in stages {
+        program.attach_shader(Shader::from_preprocessed(shader, *shader_type)?);
+    }
+    if retrievable {
+        gl::ProgramParameteri(
+            program.id,
+            gl::PROGRAM_BINARY_RETRIEVABLE_HINT,
+            gl::TRUE as i32,
+        );
+    }
+    program.link_program()?;
+    Ok(program)
+}
+
+// Polls how often a `HotReloadProgram` checks its files
+const SHADER_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+// Shader program built from files that recompiles and relinks itself when
+// one of them, includes too, changes on disk. A broken edit keeps the last
+// working program running.
+pub struct HotReloadProgram {
+    program: ShaderProgram,
+    stages: Vec<(std::path::PathBuf, ShaderType)>,
+    preprocessor: ShaderPreprocessor,
+    watched: Vec<(std::path::PathBuf, Option<std::time::SystemTime>)>,
+    last_poll: std::time::Instant,
+    cache: Option<std::rc::Rc<ProgramBinaryCache>>,
+}
+
+impl HotReloadProgram {
+    // The first build has to succeed, there is nothing to fall back to
+    pub unsafe fn new(stages: &[(&std::path::Path, ShaderType)]) -> Result<Self, String> {
+        HotReloadProgram::with_cache(stages, None)
+    }
+
+    // Same as `new`, every build goes through the binary cache when given
+    pub unsafe fn with_cache(
+        stages: &[(&std::path::Path, ShaderType)],
+        cache: Option<std::rc::Rc<ProgramBinaryCache>>,
+    ) -> Result<Self, String> {
+        HotReloadProgram::with_preprocessor(stages, ShaderPreprocessor::new(), cache)
+    }
+
+    // Builds every stage with `preprocessor`, e.g. to add defines
+    pub unsafe fn with_preprocessor(
+        stages: &[(&std::path::Path, ShaderType)],
+        preprocessor: ShaderPreprocessor,
+        cache: Option<std::rc::Rc<ProgramBinaryCache>>,
+    ) -> Result<Self, String> {
+        let mut program = HotReloadProgram {
+            program: ShaderProgram::new(),
+            stages: stages
+                .iter()
+                .map(|(path, shader_type)| (path.to_path_buf(), *shader_type))
+                .collect(),
+            preprocessor,
+            watched: Vec::new(),
+            last_poll: std::time::Instant::now(),
+            cache,
+        };
+        program.reload()?;
+        Ok(program)
+    }
+
+    // Rebuilds the program, swapping it in and carrying the uniform values
+    // over on success
+    pub unsafe fn reload(&mut self) -> Result<(), String> {
+        let mut watched = Vec::new();
+        let result: Result<Vec<_>, String> = self
+            .stages
+            .iter()
+            .map(|(path, shader_type)| {
+                let shader = self.preprocessor.process(path)?;
+                watched.extend(shader.files.iter().cloned());
+                Ok((shader, *shader_type))
+            })
+            .collect();
+        // watch whatever was found so fixing a broken file triggers a retry
+        if !watched.is_empty() {
+            watched.sort();
+            watched.dedup();
+            self.watched = watched
+                .into_iter()
+                .map(|path| {
+                    let modified = modified_time(&path);
+                    (path, modified)
+                })
+                .collect();
+        }
+        let shaders = result?;
+        let stages: Vec<_> = shaders
+            .iter()
+            .map(|(shader, shader_type)| (shader, *shader_type))
+            .collect();
+        let program = match &self.cache {
+            Some(cache) => cache.link(&stages)?,
+            None => link_stages(&stages, false)?,
+        };
+
+        let mut current_program = 0;
+        gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut current_program);
+        self.program.copy_uniforms_to(&program);
+        // copying made the new program current, restore what was in use
+        // unless that was a deleted program only kept alive by being bound
+        if current_program as u32 == self.program.id {
+            program.use_program();
+        } else if gl::IsProgram(current_program as u32) == gl::TRUE {
+            gl::UseProgram(current_program as u32);
+        } else {
+            gl::UseProgram(0);
+        }
+        self.program = program;
+        check_gl_error()
+    }
+
+    // Reloads when a watched file changed since the last check. Returns true
+    // when a new program was swapped in, on failure the previous program
+    // stays in use.
+    pub unsafe fn reload_if_changed(&mut self) -> Result<bool, String> {
+        if self.last_poll.elapsed() < SHADER_POLL_INTERVAL {
+            return Ok(false);
+        }
+        self.last_poll = std::time::Instant::now();
+        let changed = self
+            .watched
+            .iter()
+            .any(|(path, modified)| modified_time(path) != *modified);
+        if !changed {
+            return Ok(false);
+        }
+        self.reload().map_err(|e| {
+            format!(
+                "Failed to reload shader program {}:\n{}",
+                self.describe(),
+                e
+            )
+        })?;
+        Ok(true)
+    }
+
+    pub fn program(&self) -> &ShaderProgram {
+        &self.program
+    }
+
+    // Every file the program was built from, includes too
+    pub fn watched_files(&self) -> impl Iterator<Item = &std::path::Path> {
+        self.watched.iter().map(|(path, _)| path.as_path())
+    }
+
+    fn describe(&self) -> String {
+        let names: Vec<String> = self
+            .stages
+            .iter()
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        match self.preprocessor.defines() {
+            [] => names.join(" + "),
+            defines => format!("{} [{}]", names.join(" + "), defines.join(" ")),
+        }
+    }
+}
+
+impl std::ops::Deref for HotReloadProgram {
+    type Target = ShaderProgram;
+
+    fn deref(&self) -> &ShaderProgram {
+        &self.program
+    }
+}
+
+// Keyword defines selecting one variant of a program template. Sorted, so
+// the same set always finds the same variant.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VariantKey {
+    defines: Vec<&'static str>,
+}
+
+impl VariantKey {
+    pub fn new(defines: &[&'static str]) -> Self {
+        let mut defines = defines.to_vec();
+        defines.sort_unstable();
+        defines.dedup();
+        VariantKey { defines }
+    }
+
+    pub fn defines(&self) -> &[&'static str] {
+        &self.defines
+    }
+}
+
+// Variants of one program template, each compiled the first time it is asked
+// for and hot reloaded on its own. Uniform blocks bound through the cache are
+// bound in every variant, including later ones.
+pub struct ProgramCache {
+    stages: Vec<(std::path::PathBuf, ShaderType)>,
+    binary_cache: Option<std::rc::Rc<ProgramBinaryCache>>,
+    // name, binding point, size and type name of the block contents
+    uniform_blocks: Vec<(String, u32, usize, &'static str)>,
+    variants: std::collections::HashMap<VariantKey, HotReloadProgram>,
+}
+
+impl ProgramCache {
+    pub fn new(
+        stages: &[(&std::path::Path, ShaderType)],
+        binary_cache: Option<std::rc::Rc<ProgramBinaryCache>>,
+    ) -> Self {
+        ProgramCache {
+            stages: stages
+                .iter()
+                .map(|(path, shader_type)| (path.to_path_buf(), *shader_type))
+                .collect(),
+            binary_cache,
+            uniform_blocks: Vec::new(),
+            variants: std::collections::HashMap::new(),
+        }
+    }
+
+    // The variant for `key`, compiled now if it wasn't used before. A failed
+    // build isn't kept, the next call tries again.
+    pub unsafe fn get(&mut self, key: &VariantKey) -> Result<&ShaderProgram, String> {
+        if !self.variants.contains_key(key) {
+            let program = self.build(key)?;
+            self.variants.insert(key.clone(), program);
+        }
+        Ok(self.variants[key].program())
     }
 
-    pub unsafe fn set_uniform_vec3(
-        &self,
+    pub unsafe fn bind_uniform_block<T: Std140>(
+        &mut self,
         name: &str,
-        v1: f32,
-        v2: f32,
-        v3: f32,
+        buffer: &UniformBuffer<T>,
     ) -> Result<(), String> {
-        let location = self.get_uniform_location(name)?;
-        self.use_program();
-        gl::Uniform3f(location, v1, v2, v3);
-        check_gl_error()?;
+        for program in self.variants.values() {
+            program.bind_uniform_block(name, buffer)?;
+        }
+        self.uniform_blocks.retain(|(block, ..)| block != name);
+        self.uniform_blocks.push((
+            name.to_owned(),
+            buffer.binding(),
+            std::mem::size_of::<T>(),
+            std::any::type_name::<T>(),
+        ));
         Ok(())
     }
+
+    // Checks every compiled variant for changed files, a failing variant
+    // doesn't stop the others from reloading. Returns true when any variant
+    // was swapped, the errors of all failed ones otherwise.
+    pub unsafe fn reload_if_changed(&mut self) -> Result<bool, String> {
+        let mut reloaded = false;
+        let mut errors = Vec::new();
+        for program in self.variants.values_mut() {
+            match program.reload_if_changed() {
+                Ok(x) => reloaded |= x,
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.is_empty() {
+            Ok(reloaded)
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    // Keys of the variants compiled so far
+    pub fn variants(&self) -> impl Iterator<Item = &VariantKey> {
+        self.variants.keys()
+    }
+
+    unsafe fn build(&self, key: &VariantKey) -> Result<HotReloadProgram, String> {
+        let stages: Vec<(&std::path::Path, ShaderType)> = self
+            .stages
+            .iter()
+            .map(|(path, shader_type)| (path.as_path(), *shader_type))
+            .collect();
+        let preprocessor = key
+            .defines()
+            .iter()
+            .fold(ShaderPreprocessor::new(), |preprocessor, define| {
+                preprocessor.define(*define)
+            });
+        let program =
+            HotReloadProgram::with_preprocessor(&stages, preprocessor, self.binary_cache.clone())?;
+        for (name, binding, size, type_name) in &self.uniform_blocks {
+            program.bind_uniform_block_to(name, *binding, *size, type_name)?;
+        }
+        Ok(program)
+    }
+}
+
+fn modified_time(path: &std::path::Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
 }
 
 fn gl_enum_to_error(err: gl::types::GLenum) -> String {
@@ -458,6 +4992,13 @@ impl Camera {
         self.update_camera_vectors();
     }
 
+    // Angles in degrees, yaw -90 looks down -Z
+    pub fn set_rotation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-89.9, 89.9);
+        self.update_camera_vectors();
+    }
+
     pub fn get_view_matrix(&self) -> glam::Mat4 {
         glam::Mat4::look_at_rh(self.position, self.position + self.front, self.up)
     }
@@ -498,14 +5039,57 @@ impl Camera {
     }
 }
 
+// Matches `Light` in common/lighting.glsl. Cutoffs are cosines of the cone
+// angles, the last three fields are the distance attenuation terms.
 pub struct Light {
-    //pub position: glam::Vec3,
-    pub direciton: glam::Vec3,
+    pub position: glam::Vec3,
+    pub direction: glam::Vec3,
+    pub cut_off: f32,
+    pub outer_cut_off: f32,
     pub ambient: glam::Vec3,
     pub diffuse: glam::Vec3,
     pub specular: glam::Vec3,
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl UniformStruct for Light {
+    unsafe fn set_fields(&self, program: &ShaderProgram, prefix: &str) -> Result<(), String> {
+        program.set_field(prefix, "position", &self.position)?;
+        program.set_field(prefix, "direction", &self.direction)?;
+        program.set_field(prefix, "cutOff", &self.cut_off)?;
+        program.set_field(prefix, "outerCutOff", &self.outer_cut_off)?;
+        program.set_field(prefix, "ambient", &self.ambient)?;
+        program.set_field(prefix, "diffuse", &self.diffuse)?;
+        program.set_field(prefix, "specular", &self.specular)?;
+        program.set_field(prefix, "constant", &self.constant)?;
+        program.set_field(prefix, "linear", &self.linear)?;
+        program.set_field(prefix, "quadratic", &self.quadratic)
+    }
+}
+
+// Matches `Material` in common/lighting.glsl, the maps are texture units
+pub struct Material {
+    pub diffuse: i32,
+    pub specular: i32,
+    pub shininess: f32,
+}
+
+impl UniformStruct for Material {
+    unsafe fn set_fields(&self, program: &ShaderProgram, prefix: &str) -> Result<(), String> {
+        program.set_field(prefix, "diffuse", &self.diffuse)?;
+        program.set_field(prefix, "specular", &self.specular)?;
+        program.set_field(prefix, "shininess", &self.shininess)
+    }
 }
 
+// Keywords of fragment_shader.glsl, a point light without specular map when
+// none are set
+pub const LIGHT_DIRECTIONAL: &str = "LIGHT_DIRECTIONAL";
+pub const LIGHT_SPOT: &str = "LIGHT_SPOT";
+pub const USE_SPECULAR_MAP: &str = "USE_SPECULAR_MAP";
+
 pub struct Keyboard {
     pub w: bool,
     pub a: bool,
@@ -523,3 +5107,1139 @@ impl Keyboard {
         }
     }
 }
+
+// Something that can be drawn into the bound framebuffer, shared by the
+// window and headless runs
+pub trait Scene {
+    // Blocks until all assets are on the GPU so the next frame is final
+    unsafe fn finish_loading(&mut self);
+    // `time` is in seconds since the scene started
+    unsafe fn draw(&mut self, camera: &Camera, time: f32, aspect_ratio: f32) -> Result<(), String>;
+    // Rebuilds the shaders whose files changed, true when any was swapped
+    unsafe fn reload_shaders(&mut self) -> Result<bool, String> {
+        Ok(false)
+    }
+}
+
+pub const SCENE_NAMES: [&str; 3] = ["containers", "containers_sun", "containers_lamp"];
+
+pub unsafe fn load_scene(name: &str) -> Result<Box<dyn Scene>, String> {
+    match name {
+        "containers" => Ok(Box::new(ContainerScene::new(LightKind::Spot)?)),
+        "containers_sun" => Ok(Box::new(ContainerScene::new(LightKind::Directional)?)),
+        "containers_lamp" => Ok(Box::new(ContainerScene::new(LightKind::Point)?)),
+        _ => Err(format!(
+            "Unknown scene {}, expected one of {:?}",
+            name, SCENE_NAMES
+        )),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightKind {
+    // sunlight from a fixed direction
+    Directional,
+    // the light cube
+    Point,
+    // flashlight held by the camera
+    Spot,
+}
+
+impl LightKind {
+    fn define(self) -> Option<&'static str> {
+        match self {
+            LightKind::Directional => Some(LIGHT_DIRECTIONAL),
+            LightKind::Point => None,
+            LightKind::Spot => Some(LIGHT_SPOT),
+        }
+    }
+}
+
+// Textured containers lit by one light, with a light cube and a gradient sky
+pub struct ContainerScene {
+    box_programs: ProgramCache,
+    light_program: HotReloadProgram,
+    light_kind: LightKind,
+    camera_buffer: UniformBuffer<CameraBlock>,
+    vao: u32,
+    vao_light: u32,
+    texture_loader: AsyncTextureLoader,
+    texture: AsyncTexture,
+    texture_specular: AsyncTexture,
+    sky_cubemap: Cubemap,
+    skybox: Skybox,
+    light_position: glam::Vec3,
+}
+
+impl ContainerScene {
+    pub unsafe fn new(light_kind: LightKind) -> Result<Self, String> {
+        // loaded from disk so they can share `common/lighting.glsl` and be
+        // edited while the scene runs, the preprocessor falls back to the
+        // copies built into the binary when the files aren't there
+        let resolver = AssetResolver::new();
+        let shader = |name: &str| {
+            resolver
+                .shader(name)
+                .unwrap_or_else(|_| std::path::PathBuf::from(name))
+        };
+        let cache = ProgramBinaryCache::from_env()?.map(std::rc::Rc::new);
+        let mut box_programs = ProgramCache::new(
+            &[
+                (&shader("vertex_shader.glsl"), ShaderType::VertexShader),
+                (&shader("fragment_shader.glsl"), ShaderType::FragmentShader),
+            ],
+            cache.clone(),
+        );
+        let light_program = HotReloadProgram::with_cache(
+            &[
+                (
+                    &shader("vertex_shader_light.glsl"),
+                    ShaderType::VertexShader,
+                ),
+                (
+                    &shader("fragment_shader_light.glsl"),
+                    ShaderType::FragmentShader,
+                ),
+            ],
+            cache,
+        )?;
+
+        let camera_buffer = UniformBuffer::new(&CameraBlock {
+            view: glam::Mat4::IDENTITY,
+            projection: glam::Mat4::IDENTITY,
+        })?;
+        box_programs.bind_uniform_block("Camera", &camera_buffer)?;
+        // compiled now so broken shaders show up before the first frame
+        box_programs.get(&ContainerScene::box_variant(light_kind))?;
+        light_program.bind_uniform_block("Camera", &camera_buffer)?;
+
+        #[rustfmt::skip]
+        let cube_verts: [f32; 288] = [
+            // positions       // normals        // texture coords
+            -0.5, -0.5, -0.5,  0.0,  0.0, -1.0,  0.0, 0.0,
+             0.5, -0.5, -0.5,  0.0,  0.0, -1.0,  1.0, 0.0,
+             0.5,  0.5, -0.5,  0.0,  0.0, -1.0,  1.0, 1.0,
+             0.5,  0.5, -0.5,  0.0,  0.0, -1.0,  1.0, 1.0,
+            -0.5,  0.5, -0.5,  0.0,  0.0, -1.0,  0.0, 1.0,
+            -0.5, -0.5, -0.5,  0.0,  0.0, -1.0,  0.0, 0.0,
+
+            -0.5, -0.5,  0.5,  0.0,  0.0, 1.0,   0.0, 0.0,
+             0.5, -0.5,  0.5,  0.0,  0.0, 1.0,   1.0, 0.0,
+             0.5,  0.5,  0.5,  0.0,  0.0, 1.0,   1.0, 1.0,
+             0.5,  0.5,  0.5,  0.0,  0.0, 1.0,   1.0, 1.0,
+            -0.5,  0.5,  0.5,  0.0,  0.0, 1.0,   0.0, 1.0,
+            -0.5, -0.5,  0.5,  0.0,  0.0, 1.0,   0.0, 0.0,
+
+            -0.5,  0.5,  0.5, -1.0,  0.0,  0.0,  1.0, 0.0,
+            -0.5,  0.5, -0.5, -1.0,  0.0,  0.0,  1.0, 1.0,
+            -0.5, -0.5, -0.5, -1.0,  0.0,  0.0,  0.0, 1.0,
+            -0.5, -0.5, -0.5, -1.0,  0.0,  0.0,  0.0, 1.0,
+            -0.5, -0.5,  0.5, -1.0,  0.0,  0.0,  0.0, 0.0,
+            -0.5,  0.5,  0.5, -1.0,  0.0,  0.0,  1.0, 0.0,
+
+             0.5,  0.5,  0.5,  1.0,  0.0,  0.0,  1.0, 0.0,
+             0.5,  0.5, -0.5,  1.0,  0.0,  0.0,  1.0, 1.0,
+             0.5, -0.5, -0.5,  1.0,  0.0,  0.0,  0.0, 1.0,
+             0.5, -0.5, -0.5,  1.0,  0.0,  0.0,  0.0, 1.0,
+             0.5, -0.5,  0.5,  1.0,  0.0,  0.0,  0.0, 0.0,
+             0.5,  0.5,  0.5,  1.0,  0.0,  0.0,  1.0, 0.0,
+
+            -0.5, -0.5, -0.5,  0.0, -1.0,  0.0,  0.0, 1.0,
+             0.5, -0.5, -0.5,  0.0, -1.0,  0.0,  1.0, 1.0,
+             0.5, -0.5,  0.5,  0.0, -1.0,  0.0,  1.0, 0.0,
+             0.5, -0.5,  0.5,  0.0, -1.0,  0.0,  1.0, 0.0,
+            -0.5, -0.5,  0.5,  0.0, -1.0,  0.0,  0.0, 0.0,
+            -0.5, -0.5, -0.5,  0.0, -1.0,  0.0,  0.0, 1.0,
+
+            -0.5,  0.5, -0.5,  0.0,  1.0,  0.0,  0.0, 1.0,
+             0.5,  0.5, -0.5,  0.0,  1.0,  0.0,  1.0, 1.0,
+             0.5,  0.5,  0.5,  0.0,  1.0,  0.0,  1.0, 0.0,
+             0.5,  0.5,  0.5,  0.0,  1.0,  0.0,  1.0, 0.0,
+            -0.5,  0.5,  0.5,  0.0,  1.0,  0.0,  0.0, 0.0,
+            -0.5,  0.5, -0.5,  0.0,  1.0,  0.0,  0.0, 1.0
+        ];
+        let vao = create_vao(&cube_verts);
+        let vao_light = create_vao(&cube_verts);
+
+        // Textures are decoded in the background and uploaded during the
+        // first frames, a placeholder is bound until then
+        let mut texture_loader = AsyncTextureLoader::new(2)?;
+        let texture = texture_loader.load_bytes(
+            include_bytes!("./textures/container2.png"),
+            &TextureOptions::default(),
+        );
+        let texture_specular = texture_loader.load_bytes(
+            include_bytes!("./textures/container2_specular.png"),
+            &TextureOptions::default(),
+        );
+
+        // Simple sky gradient, there are no skybox images in the repo yet
+        let panorama = image::RgbImage::from_fn(256, 128, |_, y| {
+            let horizon = glam::vec3(0.6, 0.7, 0.8);
+            let zenith = glam::vec3(0.1, 0.25, 0.5);
+            let ground = glam::vec3(0.2, 0.2, 0.2);
+            let t = 1.0 - y as f32 / 63.5;
+            let color = if t >= 0.0 {
+                horizon.lerp(zenith, t)
+            } else {
+                horizon.lerp(ground, (-t * 4.0).min(1.0))
+            } * 255.0;
+            image::Rgb([color.x as u8, color.y as u8, color.z as u8])
+        });
+        let sky_cubemap = Cubemap::from_equirect(
+            image::DynamicImage::ImageRgb8(panorama),
+            256,
+            &TextureOptions::default().mipmaps(false),
+        )?;
+        let skybox = Skybox::new()?;
+
+        Ok(ContainerScene {
+            box_programs,
+            light_program,
+            light_kind,
+            camera_buffer,
+            vao,
+            vao_light,
+            texture_loader,
+            texture,
+            texture_specular,
+            sky_cubemap,
+            skybox,
+            light_position: glam::vec3(1.2, 1.0, 2.0),
+        })
+    }
+
+    fn box_variant(light_kind: LightKind) -> VariantKey {
+        match light_kind.define() {
+            Some(define) => VariantKey::new(&[define, USE_SPECULAR_MAP]),
+            None => VariantKey::new(&[USE_SPECULAR_MAP]),
+        }
+    }
+}
+
+impl Scene for ContainerScene {
+    unsafe fn finish_loading(&mut self) {
+        self.texture_loader.wait_for_uploads();
+    }
+
+    unsafe fn reload_shaders(&mut self) -> Result<bool, String> {
+        // the light is checked even when a box variant failed
+        match (
+            self.box_programs.reload_if_changed(),
+            self.light_program.reload_if_changed(),
+        ) {
+            (Ok(boxes), Ok(light)) => Ok(boxes || light),
+            (Err(boxes), Err(light)) => Err(format!("{}\n{}", boxes, light)),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        }
+    }
+
+    unsafe fn draw(
+        &mut self,
+        camera: &Camera,
+        _time: f32,
+        aspect_ratio: f32,
+    ) -> Result<(), String> {
+        self.texture_loader
+            .pump_uploads(std::time::Duration::from_millis(4));
+
+        gl::Enable(gl::DEPTH_TEST);
+        gl::ClearColor(0.2, 0.3, 0.3, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+        let projection =
+            glam::Mat4::perspective_rh(f32::to_radians(camera.zoom), aspect_ratio, 0.1, 100.0);
+        let view = camera.get_view_matrix();
+        self.camera_buffer.update(&CameraBlock { view, projection });
+
+        // Drawing center cube
+        let program = self
+            .box_programs
+            .get(&ContainerScene::box_variant(self.light_kind))?;
+        program.use_program();
+        let (position, direction) = match self.light_kind {
+            LightKind::Directional => (glam::Vec3::ZERO, glam::vec3(-0.2, -1.0, -0.3)),
+            LightKind::Point => (self.light_position, glam::Vec3::ZERO),
+            LightKind::Spot => (camera.position, camera.front),
+        };
+        let light = Light {
+            position,
+            direction,
+            cut_off: 12.5_f32.to_radians().cos(),
+            outer_cut_off: 17.5_f32.to_radians().cos(),
+            ambient: glam::vec3(0.2, 0.2, 0.2),
+            diffuse: glam::vec3(0.5, 0.5, 0.5),
+            specular: glam::vec3(1.0, 1.0, 1.0),
+            constant: 1.0,
+            linear: 0.09,
+            quadratic: 0.032,
+        };
+        let material = Material {
+            diffuse: 0,
+            specular: 1,
+            shininess: 32.0,
+        };
+        program.set_struct("light", &light)?;
+        program.set_struct("material", &material)?;
+        program.set_uniform("viewPos", &camera.position)?;
+
+        gl::BindVertexArray(self.vao);
+        self.texture.bind(0);
+        self.texture_specular.bind(1);
+
+        #[rustfmt::skip]
+        let cube_postiions: [glam::Vec3; 10] = [
+            glam::vec3( 0.0,  0.0,  0.0),
+            glam::vec3( 2.0,  5.0, -15.0),
+            glam::vec3(-1.5, -2.2, -2.5),
+            glam::vec3(-3.8, -2.0, -12.3),
+            glam::vec3( 2.4, -0.4, -3.5),
+            glam::vec3(-1.7,  3.0, -7.5),
+            glam::vec3( 1.3, -2.0, -2.5),
+            glam::vec3( 1.5,  2.0, -2.5),
+            glam::vec3( 1.5,  0.2, -1.5),
+            glam::vec3(-1.3,  1.0, -1.5)
+        ];
+        for (idx, pos) in cube_postiions.iter().enumerate() {
+            let angle = 20.0 * idx as f32;
+            let rotation =
+                glam::Quat::from_axis_angle(glam::vec3(1.0, 0.3, 0.5).normalize(), angle);
+            let model = glam::Mat4::from_rotation_translation(rotation, *pos);
+            program.set_uniform("model", &model)?;
+            gl::DrawArrays(gl::TRIANGLES, 0, 36);
+        }
+
+        // Drawing light source cube
+        self.light_program.use_program();
+        gl::BindVertexArray(self.vao_light);
+        let model = glam::Mat4::from_scale_rotation_translation(
+            glam::vec3(0.2, 0.2, 0.2),
+            glam::Quat::IDENTITY,
+            self.light_position,
+        );
+        self.light_program.set_uniform("model", &model)?;
+        gl::DrawArrays(gl::TRIANGLES, 0, 36);
+
+        // Drawing the sky last so it only covers the background
+        self.skybox.draw(&self.sky_cubemap, &view, &projection)?;
+
+        // clean up
+        gl::BindVertexArray(0);
+        check_gl_error()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Empty directory for one test, removed from earlier runs
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("learnopengl-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn decode_block(format: CompressedFormat, block: &[u8]) -> Vec<u8> {
+        CompressedImage::new(format, 4, 4, vec![block.to_vec()])
+            .unwrap()
+            .decode_level(0)
+    }
+
+    fn texels(pixels: &[u8]) -> Vec<[u8; 4]> {
+        pixels
+            .chunks_exact(4)
+            .map(|x| x.try_into().unwrap())
+            .collect()
+    }
+
+    // Indices 0..7 for texels 0..7 and again for 8..15, three bits each
+    const BC4_INDICES: [u8; 6] = [0x88, 0xC6, 0xFA, 0x88, 0xC6, 0xFA];
+
+    #[test]
+    fn bc1_four_color_block() {
+        // red and blue endpoints, each row has indices 0, 1, 2, 3
+        let pixels = decode_block(
+            CompressedFormat::Bc1,
+            &[0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4],
+        );
+        let row = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+            [170, 0, 85, 255],
+            [85, 0, 170, 255],
+        ];
+        assert_eq!(texels(&pixels), row.repeat(4));
+    }
+
+    #[test]
+    fn bc1_three_color_block() {
+        // c0 <= c1 gives the midpoint and transparent black
+        let pixels = decode_block(
+            CompressedFormat::Bc1,
+            &[0x00, 0x00, 0x00, 0x84, 0xE4, 0xE4, 0xE4, 0xE4],
+        );
+        let row = [
+            [0, 0, 0, 255],
+            [132, 130, 0, 255],
+            [66, 65, 0, 255],
+            [0, 0, 0, 0],
+        ];
+        assert_eq!(texels(&pixels), row.repeat(4));
+    }
+
+    #[test]
+    fn bc3_block() {
+        // alpha 210 to 0 in sevenths is exact, the color block always uses
+        // four colors, green to blue with indices 3, 2, 1, 0
+        let mut block = vec![210, 0];
+        block.extend_from_slice(&BC4_INDICES);
+        block.extend_from_slice(&[0xE0, 0x07, 0x1F, 0x00, 0x1B, 0x1B, 0x1B, 0x1B]);
+        let pixels = decode_block(CompressedFormat::Bc3, &block);
+        let colors = [[0, 85, 170], [0, 170, 85], [0, 0, 255], [0, 255, 0]];
+        let alphas = [210, 0, 180, 150, 120, 90, 60, 30];
+        let expected: Vec<[u8; 4]> = (0..16)
+            .map(|x| {
+                let [r, g, b] = colors[x % 4];
+                [r, g, b, alphas[x % 8]]
+            })
+            .collect();
+        assert_eq!(texels(&pixels), expected);
+    }
+
+    #[test]
+    fn bc4_eight_value_block() {
+        let mut block = vec![210, 0];
+        block.extend_from_slice(&BC4_INDICES);
+        let pixels = decode_block(CompressedFormat::Bc4, &block);
+        assert_eq!(pixels, [210, 0, 180, 150, 120, 90, 60, 30].repeat(2));
+    }
+
+    #[test]
+    fn bc4_six_value_block() {
+        // r0 <= r1 interpolates in fifths and adds 0 and 255
+        let mut block = vec![0, 250];
+        block.extend_from_slice(&BC4_INDICES);
+        let pixels = decode_block(CompressedFormat::Bc4, &block);
+        assert_eq!(pixels, [0, 250, 50, 100, 150, 200, 0, 255].repeat(2));
+    }
+
+    #[test]
+    fn bc5_block() {
+        let mut block = vec![210, 0];
+        block.extend_from_slice(&BC4_INDICES);
+        block.extend_from_slice(&[0, 250]);
+        block.extend_from_slice(&BC4_INDICES);
+        let pixels = decode_block(CompressedFormat::Bc5, &block);
+        let red = [210, 0, 180, 150, 120, 90, 60, 30];
+        let green = [0, 250, 50, 100, 150, 200, 0, 255];
+        let expected: Vec<u8> = (0..16).flat_map(|x| [red[x % 8], green[x % 8]]).collect();
+        assert_eq!(pixels, expected);
+    }
+
+    // name, block and the expected texels
+    type Bc7Case = (&'static str, [u8; 16], [[u8; 4]; 16]);
+
+    // Blocks for every mode with the texels Mesa decodes them to
+    #[rustfmt::skip]
+    const BC7_BLOCKS: [Bc7Case; 9] = [
+    // three subsets, partition 0, per endpoint p-bits
+    ("mode0",
+     [0x01, 0x8C, 0x05, 0x7D, 0xE3, 0x7A, 0xF2, 0x7A, 0xF2, 0x4B, 0xCD, 0xA3, 0x3A, 0xE3, 0xA1, 0x3A],
+     [[15, 159, 130, 255], [92, 46, 207, 255], [132, 160, 94, 255], [198, 115, 49, 255],
+      [77, 68, 192, 255], [30, 137, 145, 255], [41, 222, 156, 255], [107, 177, 111, 255],
+      [15, 159, 130, 255], [224, 141, 112, 255], [177, 94, 181, 255], [198, 115, 49, 255],
+      [209, 126, 134, 255], [162, 79, 203, 255], [239, 156, 90, 255], [132, 49, 247, 255]]),
+    // two subsets, partition 17, shared p-bits
+    ("mode1",
+     [0x46, 0x9C, 0x8C, 0x78, 0x98, 0x4B, 0x68, 0x53, 0xFA, 0x57, 0xE6, 0x51, 0x9D, 0xF1, 0x50, 0x9D],
+     [[125, 109, 89, 255], [110, 94, 110, 255], [71, 55, 184, 255], [34, 18, 255, 255],
+      [176, 160, 140, 255], [137, 121, 101, 255], [201, 185, 165, 255], [85, 69, 157, 255],
+      [125, 109, 89, 255], [188, 172, 152, 255], [150, 134, 114, 255], [112, 96, 76, 255],
+      [176, 160, 140, 255], [137, 121, 101, 255], [201, 185, 165, 255], [163, 147, 127, 255]]),
+    // three subsets, partition 1, no p-bits
+    ("mode2",
+     [0x0C, 0xB0, 0x23, 0x1A, 0x1A, 0xAA, 0x80, 0x65, 0x02, 0x1A, 0x2E, 0x91, 0xEF, 0x72, 0x3A, 0x39],
+     [[171, 138, 105, 255], [142, 109, 76, 255], [115, 82, 49, 255], [33, 0, 231, 255],
+      [171, 138, 105, 255], [142, 109, 76, 255], [214, 181, 148, 255], [33, 0, 231, 255],
+      [105, 72, 125, 255], [76, 43, 188, 255], [214, 181, 148, 255], [33, 0, 231, 255],
+      [105, 72, 125, 255], [76, 43, 188, 255], [49, 16, 247, 255], [33, 0, 231, 255]]),
+    // two subsets, partition 0, per endpoint p-bits
+    ("mode3",
+     [0x08, 0x50, 0xD4, 0x40, 0x0B, 0xD4, 0xC4, 0x97, 0x32, 0xC4, 0x38, 0x47, 0x75, 0x72, 0x72, 0x72],
+     [[97, 133, 81, 255], [156, 104, 140, 255], [44, 164, 28, 255], [129, 249, 113, 255],
+      [97, 133, 81, 255], [156, 104, 140, 255], [44, 164, 28, 255], [129, 249, 113, 255],
+      [97, 133, 81, 255], [156, 104, 140, 255], [44, 164, 28, 255], [129, 249, 113, 255],
+      [97, 133, 81, 255], [156, 104, 140, 255], [44, 164, 28, 255], [129, 249, 113, 255]]),
+    // separate alpha, no rotation, 2 bit color indices
+    ("mode4",
+     [0x10, 0xF1, 0x30, 0x81, 0xFC, 0x94, 0x76, 0x72, 0x72, 0x72, 0x2C, 0xE6, 0xF0, 0x2A, 0xE6, 0xF0],
+     [[113, 72, 125, 102], [84, 43, 188, 141], [57, 16, 247, 77], [140, 99, 66, 115],
+      [113, 72, 125, 153], [84, 43, 188, 90], [57, 16, 247, 128], [140, 99, 66, 166],
+      [113, 72, 125, 102], [84, 43, 188, 141], [57, 16, 247, 77], [140, 99, 66, 115],
+      [113, 72, 125, 153], [84, 43, 188, 90], [57, 16, 247, 128], [140, 99, 66, 166]]),
+    // red swapped with alpha, 3 bit color indices
+    ("mode4_rotation_index_selection",
+     [0xB0, 0xF1, 0x30, 0x81, 0xFC, 0x94, 0x76, 0x72, 0x72, 0x72, 0x2C, 0xE6, 0xF0, 0x2A, 0xE6, 0xF0],
+     [[106, 76, 117, 117], [137, 39, 196, 80], [166, 99, 66, 140], [77, 64, 142, 105],
+      [106, 28, 222, 69], [137, 87, 91, 128], [166, 51, 171, 92], [77, 16, 247, 57],
+      [106, 76, 117, 117], [137, 39, 196, 80], [166, 99, 66, 140], [77, 64, 142, 105],
+      [106, 28, 222, 69], [137, 87, 91, 128], [166, 51, 171, 92], [77, 16, 247, 57]]),
+    // green swapped with alpha
+    ("mode5_rotation",
+     [0xA0, 0xCD, 0x51, 0xE2, 0x5B, 0xDC, 0xC0, 0x1B, 0x75, 0x72, 0x72, 0x72, 0xC4, 0xC6, 0xC6, 0xC6],
+     [[127, 240, 111, 75], [98, 184, 82, 134], [70, 240, 54, 191], [155, 70, 139, 18],
+      [127, 126, 111, 75], [98, 184, 82, 134], [70, 240, 54, 191], [155, 70, 139, 18],
+      [127, 126, 111, 75], [98, 184, 82, 134], [70, 240, 54, 191], [155, 70, 139, 18],
+      [127, 126, 111, 75], [98, 184, 82, 134], [70, 240, 54, 191], [155, 70, 139, 18]]),
+    // one subset, 4 bit indices
+    ("mode6",
+     [0xC0, 0xD4, 0xBF, 0xBC, 0x0B, 0xDD, 0x99, 0x22, 0x63, 0x0B, 0xA5, 0x4F, 0xE9, 0x83, 0x2D, 0xC7],
+     [[93, 197, 77, 147], [152, 168, 136, 118], [209, 141, 193, 91], [82, 202, 66, 152],
+      [139, 175, 123, 125], [198, 146, 182, 96], [255, 119, 239, 69], [128, 180, 112, 130],
+      [185, 153, 169, 103], [244, 124, 228, 74], [117, 185, 101, 135], [174, 158, 158, 108],
+      [231, 131, 215, 81], [106, 190, 90, 140], [163, 163, 147, 113], [220, 136, 204, 86]]),
+    // two subsets with alpha
+    ("mode7",
+     [0x80, 0x40, 0xE1, 0x12, 0x85, 0xDB, 0x46, 0x9F, 0xE9, 0x23, 0x4F, 0x55, 0x75, 0x72, 0x72, 0x72],
+     [[104, 69, 211, 126], [167, 129, 182, 186], [65, 24, 251, 81], [150, 109, 77, 166],
+      [104, 69, 211, 126], [167, 129, 182, 186], [65, 24, 251, 81], [150, 109, 77, 166],
+      [104, 69, 211, 126], [167, 129, 182, 186], [65, 24, 251, 81], [150, 109, 77, 166],
+      [104, 69, 211, 126], [167, 129, 182, 186], [65, 24, 251, 81], [150, 109, 77, 166]]),
+    ];
+
+    #[test]
+    fn bc7_blocks() {
+        for (name, block, expected) in &BC7_BLOCKS {
+            let pixels = decode_block(CompressedFormat::Bc7, block);
+            assert_eq!(texels(&pixels), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn bc7_reserved_mode_is_transparent_black() {
+        let pixels = decode_block(CompressedFormat::Bc7, &[0; 16]);
+        assert_eq!(pixels, vec![0; 64]);
+    }
+
+    // Header of a 4x4 KTX2 file with the given level index entries
+    fn ktx2_header(vk_format: u32, levels: &[(u64, u64)]) -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for value in [vk_format, 1, 4, 4, 0, 0, 1, levels.len() as u32, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.resize(80, 0);
+        for (offset, length) in levels {
+            for value in [*offset, *length, *length] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    // Header of a 4x4 DDS file, `dx10` is the DXGI format, misc flag and
+    // array size of the DX10 extension
+    fn dds_header(four_cc: &[u8; 4], caps2: u32, dx10: Option<(u32, u32, u32)>) -> Vec<u8> {
+        let mut bytes = b"DDS ".to_vec();
+        bytes.resize(128, 0);
+        bytes[12..16].copy_from_slice(&4u32.to_le_bytes());
+        bytes[16..20].copy_from_slice(&4u32.to_le_bytes());
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes[112..116].copy_from_slice(&caps2.to_le_bytes());
+        if let Some((dxgi_format, misc_flag, array_size)) = dx10 {
+            for value in [dxgi_format, 3, misc_flag, array_size, 0] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn ktx2_loads_bc1_level() {
+        let mut bytes = ktx2_header(131, &[(104, 8)]);
+        bytes.extend_from_slice(&[0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4]);
+        let image = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.format, CompressedFormat::Bc1);
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!(image.levels, vec![bytes[104..].to_vec()]);
+    }
+
+    #[test]
+    fn ktx2_truncated_header_is_an_error() {
+        let bytes = ktx2_header(131, &[(104, 8)]);
+        for len in [12, 30, 48, 90] {
+            assert!(
+                CompressedImage::from_ktx2(&bytes[..len]).is_err(),
+                "{}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn ktx2_unsupported_format_is_an_error() {
+        // VK_FORMAT_R8G8B8A8_UNORM
+        let mut bytes = ktx2_header(37, &[(104, 64)]);
+        bytes.resize(168, 0);
+        let err = CompressedImage::from_ktx2(&bytes).err().unwrap();
+        assert!(err.contains("VkFormat 37"), "{}", err);
+    }
+
+    #[test]
+    fn ktx2_level_out_of_bounds_is_an_error() {
+        for level in [(104, 9), (u64::MAX - 4, 8), (8, u64::MAX)] {
+            let mut bytes = ktx2_header(131, &[level]);
+            bytes.extend_from_slice(&[0; 8]);
+            let err = CompressedImage::from_ktx2(&bytes).err().unwrap();
+            assert!(err.contains("out of bounds"), "{:?}: {}", level, err);
+        }
+    }
+
+    #[test]
+    fn dds_loads_dxt5_level() {
+        let mut bytes = dds_header(b"DXT5", 0, None);
+        bytes.extend_from_slice(&[7; 16]);
+        let image = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.format, CompressedFormat::Bc3);
+        assert_eq!(image.levels, vec![vec![7; 16]]);
+    }
+
+    #[test]
+    fn dds_truncated_header_is_an_error() {
+        let bytes = dds_header(b"DX10", 0, Some((98, 0, 1)));
+        for len in [4, 20, 86, 130, 148] {
+            assert!(CompressedImage::from_dds(&bytes[..len]).is_err(), "{}", len);
+        }
+    }
+
+    #[test]
+    fn ktx2_huge_level_count_is_an_error() {
+        for level_count in [4, 33, u32::MAX] {
+            let mut bytes = ktx2_header(131, &[(104, 8)]);
+            bytes[40..44].copy_from_slice(&level_count.to_le_bytes());
+            let err = CompressedImage::from_ktx2(&bytes).err().unwrap();
+            assert!(err.contains("at most 3"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn dds_huge_mip_count_is_an_error() {
+        const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+        for mip_count in [4, 33, u32::MAX] {
+            let mut bytes = dds_header(b"DXT1", 0, None);
+            bytes[8..12].copy_from_slice(&DDSD_MIPMAPCOUNT.to_le_bytes());
+            bytes[28..32].copy_from_slice(&mip_count.to_le_bytes());
+            bytes.resize(128 + 8, 0);
+            let err = CompressedImage::from_dds(&bytes).err().unwrap();
+            assert!(err.contains("at most 3"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn level_size_past_the_chain_is_one_texel() {
+        let image = CompressedImage::new(CompressedFormat::Bc1, 4, 4, Vec::new()).unwrap();
+        assert_eq!(image.level_size(2), (1, 1));
+        assert_eq!(image.level_size(32), (1, 1));
+        assert_eq!(image.level_size(usize::MAX), (1, 1));
+    }
+
+    #[test]
+    fn dds_unsupported_format_is_an_error() {
+        // DXGI_FORMAT_R8G8B8A8_UNORM
+        let mut bytes = dds_header(b"DX10", 0, Some((28, 0, 1)));
+        bytes.resize(148 + 64, 0);
+        let err = CompressedImage::from_dds(&bytes).err().unwrap();
+        assert!(err.contains("DXGI format 28"), "{}", err);
+
+        let bytes = dds_header(b"RGBG", 0, None);
+        assert!(CompressedImage::from_dds(&bytes).is_err());
+    }
+
+    #[test]
+    fn dds_cubemaps_and_arrays_are_errors() {
+        let cases = [
+            dds_header(b"DXT1", 0x200 | 0xFC00, None),
+            dds_header(b"DXT1", 0x200000, None),
+            dds_header(b"DX10", 0, Some((71, 0x4, 1))),
+            dds_header(b"DX10", 0, Some((71, 0, 6))),
+        ];
+        for mut bytes in cases {
+            // enough data for all faces, only the flags are wrong
+            bytes.resize(bytes.len() + 8 * 6, 0);
+            let err = CompressedImage::from_dds(&bytes).err().unwrap();
+            assert!(err.contains("not supported"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn asset_resolver_searches_roots_in_order() {
+        let dir = temp_dir("assets");
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        std::fs::create_dir_all(second.join("textures")).unwrap();
+        std::fs::write(second.join("textures/wall.png"), b"").unwrap();
+
+        // the roots $LEARNOPENGL_ASSETS would give, without touching the
+        // environment other tests read
+        let paths = env::join_paths([&first, &second]).unwrap();
+        let resolver = AssetResolver::with_roots(AssetResolver::default_roots(Some(&paths)));
+
+        assert_eq!(&resolver.roots()[..2], [first.clone(), second.clone()]);
+        assert_eq!(
+            resolver.texture("wall.png").unwrap(),
+            second.join("textures/wall.png")
+        );
+        let err = resolver.texture("missing.png").unwrap_err();
+        let AssetError::NotFound { kind, name, tried } = &err;
+        assert_eq!(*kind, AssetKind::Texture);
+        assert_eq!(name, "missing.png");
+        let expected: Vec<std::path::PathBuf> = resolver
+            .roots()
+            .iter()
+            .map(|root| root.join("textures/missing.png"))
+            .collect();
+        assert_eq!(*tried, expected);
+        assert_eq!(tried[0], first.join("textures/missing.png"));
+        let message = err.to_string();
+        for path in tried {
+            assert!(message.contains(&path.display().to_string()), "{}", message);
+        }
+    }
+
+    #[test]
+    fn civil_from_days_known_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        // leap day in a year divisible by 400
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        // the last day a 32 bit time_t reaches
+        assert_eq!(civil_from_days(24_855), (2038, 1, 19));
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Vec3FloatBlock {
+        direction: glam::Vec3,
+        intensity: f32,
+    }
+
+    std140_block!(Vec3FloatBlock {
+        direction: glam::Vec3,
+        intensity: f32,
+    });
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct MatrixBlock {
+        scale: f32,
+        model: glam::Mat4,
+        bias: f32,
+    }
+
+    std140_block!(MatrixBlock {
+        scale: f32,
+        model: glam::Mat4,
+        bias: f32,
+    });
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Vec3ArrayBlock {
+        count: u32,
+        points: [glam::Vec3A; 3],
+        radius: f32,
+    }
+
+    std140_block!(Vec3ArrayBlock {
+        count: u32,
+        points: [glam::Vec3A; 3],
+        radius: f32,
+    });
+
+    #[test]
+    fn std140_float_packs_after_vec3() {
+        // a vec3 is 16 byte aligned but only 12 bytes, a scalar fills the gap
+        assert_eq!(std::mem::offset_of!(Vec3FloatBlock, direction), 0);
+        assert_eq!(std::mem::offset_of!(Vec3FloatBlock, intensity), 12);
+        assert_eq!(std::mem::size_of::<Vec3FloatBlock>(), 16);
+    }
+
+    #[test]
+    fn std140_mat4_is_16_byte_aligned() {
+        assert_eq!(std::mem::offset_of!(MatrixBlock, scale), 0);
+        assert_eq!(std::mem::offset_of!(MatrixBlock, model), 16);
+        assert_eq!(std::mem::offset_of!(MatrixBlock, bias), 80);
+        // block size rounds up to a multiple of the vec4 alignment
+        assert_eq!(std::mem::size_of::<MatrixBlock>(), 96);
+    }
+
+    #[test]
+    fn std140_vec3_array_elements_take_16_bytes() {
+        assert_eq!(<[glam::Vec3A; 3] as Std140Field>::SIZE, 48);
+        assert_eq!(std::mem::offset_of!(Vec3ArrayBlock, count), 0);
+        assert_eq!(std::mem::offset_of!(Vec3ArrayBlock, points), 16);
+        assert_eq!(std::mem::offset_of!(Vec3ArrayBlock, radius), 64);
+        assert_eq!(std::mem::size_of::<Vec3ArrayBlock>(), 80);
+    }
+
+    #[test]
+    fn camera_block_matches_std140() {
+        assert_eq!(std::mem::offset_of!(CameraBlock, view), 0);
+        assert_eq!(std::mem::offset_of!(CameraBlock, projection), 64);
+        assert_eq!(std::mem::size_of::<CameraBlock>(), 128);
+    }
+
+    fn write_shaders(dir: &std::path::Path, files: &[(&str, &str)]) {
+        for (name, source) in files {
+            std::fs::write(dir.join(name), source).unwrap();
+        }
+    }
+
+    #[test]
+    fn preprocessor_numbers_nested_includes() {
+        let dir = temp_dir("nested-includes");
+        write_shaders(
+            &dir,
+            &[
+                (
+                    "main.glsl",
+                    "#version 330 core\n#include \"a.glsl\"\nvoid main() {}\n",
+                ),
+                ("a.glsl", "float a;\n#include \"b.glsl\"\nfloat a2;\n"),
+                ("b.glsl", "float b;\n"),
+            ],
+        );
+        let shader = ShaderPreprocessor::with_root(&dir)
+            .process(&dir.join("main.glsl"))
+            .unwrap();
+        assert_eq!(
+            shader.source,
+            "#version 330 core\n#line 2 0\n\
+             #line 1 1\nfloat a;\n\
+             #line 1 2\nfloat b;\n\
+             #line 3 1\nfloat a2;\n\
+             #line 3 0\nvoid main() {}\n"
+        );
+        let dir = dir.canonicalize().unwrap();
+        assert_eq!(
+            shader.files,
+            [
+                dir.join("main.glsl"),
+                dir.join("a.glsl"),
+                dir.join("b.glsl")
+            ]
+        );
+    }
+
+    #[test]
+    fn preprocessor_names_the_include_cycle() {
+        let dir = temp_dir("include-cycle");
+        write_shaders(
+            &dir,
+            &[
+                ("a.glsl", "#include \"b.glsl\"\n"),
+                ("b.glsl", "#include \"a.glsl\"\n"),
+            ],
+        );
+        let err = ShaderPreprocessor::with_root(&dir)
+            .process(&dir.join("a.glsl"))
+            .unwrap_err();
+        let dir = dir.canonicalize().unwrap();
+        let (a, b) = (dir.join("a.glsl"), dir.join("b.glsl"));
+        assert_eq!(
+            err,
+            format!(
+                "Shader include cycle: {} -> {} -> {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )
+        );
+    }
+
+    #[test]
+    fn preprocessor_lists_tried_paths_for_missing_include() {
+        let dir = temp_dir("missing-include");
+        write_shaders(
+            &dir,
+            &[("main.glsl", "float x;\n#include \"missing.glsl\"\n")],
+        );
+        let err = ShaderPreprocessor::with_root(&dir)
+            .process(&dir.join("main.glsl"))
+            .unwrap_err();
+        let location = format!(
+            "{}:2: ",
+            dir.canonicalize().unwrap().join("main.glsl").display()
+        );
+        assert!(err.starts_with(&location), "{}", err);
+        assert!(err.contains("missing.glsl"), "{}", err);
+        assert!(
+            err.contains(&dir.join("missing.glsl").display().to_string()),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn preprocessor_rejects_version_in_include() {
+        let dir = temp_dir("include-version");
+        write_shaders(
+            &dir,
+            &[
+                ("main.glsl", "#version 330 core\n#include \"a.glsl\"\n"),
+                ("a.glsl", "#version 330 core\n"),
+            ],
+        );
+        let err = ShaderPreprocessor::with_root(&dir)
+            .process(&dir.join("main.glsl"))
+            .unwrap_err();
+        let location = format!("{}:1", dir.canonicalize().unwrap().join("a.glsl").display());
+        assert_eq!(
+            err,
+            format!("{}: included files can't set #version", location)
+        );
+    }
+
+    #[test]
+    fn preprocessor_includes_again_without_pragma_once() {
+        let dir = temp_dir("pragma-once");
+        write_shaders(
+            &dir,
+            &[
+                ("main.glsl", "#include \"a.glsl\"\n#include \"a.glsl\"\n"),
+                ("a.glsl", "float a;\n"),
+                ("once.glsl", "#include \"b.glsl\"\n#include \"b.glsl\"\n"),
+                ("b.glsl", "#pragma once\nfloat b;\n"),
+            ],
+        );
+        let preprocessor = ShaderPreprocessor::with_root(&dir);
+        let shader = preprocessor.process(&dir.join("main.glsl")).unwrap();
+        assert_eq!(
+            shader.source,
+            "#line 1 1\nfloat a;\n#line 2 0\n#line 1 1\nfloat a;\n#line 3 0\n"
+        );
+        assert_eq!(shader.files.len(), 2);
+        let shader = preprocessor.process(&dir.join("once.glsl")).unwrap();
+        assert_eq!(
+            shader.source,
+            "#line 1 1\n\nfloat b;\n#line 2 0\n#line 3 0\n"
+        );
+    }
+
+    #[test]
+    fn preprocessor_falls_back_to_embedded_files() {
+        let dir = temp_dir("embedded-shaders");
+        write_shaders(&dir, &[("main.glsl", "#include \"common.glsl\"\n")]);
+        let preprocessor = ShaderPreprocessor::with_root(&dir)
+            .embed("common.glsl", "float common;\n")
+            .embed(
+                "embedded.glsl",
+                "#include \"common.glsl\"\nfloat embedded;\n",
+            );
+        let shader = preprocessor.process(&dir.join("main.glsl")).unwrap();
+        assert_eq!(shader.source, "#line 1 1\nfloat common;\n#line 2 0\n");
+        assert_eq!(shader.files[1], std::path::Path::new("common.glsl"));
+        let shader = preprocessor
+            .process(std::path::Path::new("embedded.glsl"))
+            .unwrap();
+        assert_eq!(
+            shader.source,
+            "#line 1 1\nfloat common;\n#line 2 0\nfloat embedded;\n"
+        );
+        // every shader the scenes load is built in
+        for name in ["vertex_shader.glsl", "fragment_shader.glsl"] {
+            ShaderPreprocessor::with_root(&dir)
+                .process(std::path::Path::new(name))
+                .unwrap_err();
+            ShaderPreprocessor::new()
+                .process(std::path::Path::new(name))
+                .unwrap();
+        }
+    }
+
+    fn binary_cache_with_entries(name: &str, max_bytes: u64) -> ProgramBinaryCache {
+        let dir = temp_dir(name);
+        let now = std::time::SystemTime::now();
+        // a.bin is the least recently used
+        for (idx, entry) in ["a.bin", "b.bin", "c.bin"].iter().enumerate() {
+            let path = dir.join(entry);
+            std::fs::write(&path, [0u8; 100]).unwrap();
+            let modified = now - std::time::Duration::from_secs(60 * (3 - idx as u64));
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), "not a cache entry").unwrap();
+        ProgramBinaryCache {
+            dir,
+            driver: String::new(),
+            max_bytes,
+        }
+    }
+
+    fn cache_files(cache: &ProgramBinaryCache) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(cache.dir())
+            .unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn binary_cache_prunes_least_recently_used_entries() {
+        let cache = binary_cache_with_entries("binary-cache-prune", 250);
+        cache.prune().unwrap();
+        assert_eq!(cache_files(&cache), ["b.bin", "c.bin", "notes.txt"]);
+        // already under the limit
+        cache.prune().unwrap();
+        assert_eq!(cache_files(&cache), ["b.bin", "c.bin", "notes.txt"]);
+    }
+
+    #[test]
+    fn binary_cache_clear_only_deletes_entries() {
+        let cache = binary_cache_with_entries("binary-cache-clear", DEFAULT_SHADER_CACHE_MAX_BYTES);
+        cache.clear().unwrap();
+        assert_eq!(cache_files(&cache), ["notes.txt"]);
+    }
+
+    #[test]
+    fn diagnostic_parses_mesa_log_line() {
+        assert_eq!(
+            ShaderDiagnostic::parse("0:12(5): error: `foo' undeclared"),
+            Some(ShaderDiagnostic {
+                source: 0,
+                file: None,
+                line: Some(12),
+                column: Some(5),
+                message: "error: `foo' undeclared".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn diagnostic_parses_nvidia_log_line() {
+        assert_eq!(
+            ShaderDiagnostic::parse("1(12) : error C1008: undefined variable \"foo\""),
+            Some(ShaderDiagnostic {
+                source: 1,
+                file: None,
+                line: Some(12),
+                column: None,
+                message: "error C1008: undefined variable \"foo\"".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn diagnostic_parses_amd_log_line() {
+        assert_eq!(
+            ShaderDiagnostic::parse("ERROR: 0:12: 'foo' : undeclared identifier"),
+            Some(ShaderDiagnostic {
+                source: 0,
+                file: None,
+                line: Some(12),
+                column: None,
+                message: "error: 'foo' : undeclared identifier".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn unparseable_log_line_is_kept_as_message() {
+        let line = "ERROR: 1 compilation errors.  No code generated.";
+        assert_eq!(ShaderDiagnostic::parse(line), None);
+        let err = ShaderError::from_log(
+            ShaderType::FragmentShader,
+            &format!("{}\n\n", line),
+            &[],
+            &["void main() {}\n".to_owned()],
+        );
+        assert_eq!(
+            err.diagnostics,
+            [ShaderDiagnostic {
+                source: 0,
+                file: None,
+                line: None,
+                column: None,
+                message: line.to_owned(),
+            }]
+        );
+        assert_eq!(
+            err.to_string(),
+            format!("Failed to compile fragment shader\n{}", line)
+        );
+    }
+
+    fn snippet_error(log: &str) -> ShaderError {
+        ShaderError::from_log(
+            ShaderType::FragmentShader,
+            log,
+            &["main.glsl".into(), "common.glsl".into()],
+            &[
+                "#version 330 core\nvoid main() {}\n".to_owned(),
+                "float a;\nfloat b;\nfloat c;\nfloat d;\n".to_owned(),
+            ],
+        )
+    }
+
+    #[test]
+    fn snippet_at_first_line_of_file() {
+        let err = snippet_error("1:1(7): error: `a' redeclared");
+        assert_eq!(
+            err.to_string(),
+            "Failed to compile fragment shader\n\
+             common.glsl:1:7: error: `a' redeclared\n\
+             >1 | float a;\n   |       ^\n 2 | float b;\n 3 | float c;"
+        );
+    }
+
+    #[test]
+    fn snippet_at_last_line_of_file() {
+        let err = snippet_error("1(4) : error C1008: undefined variable \"d\"");
+        assert_eq!(
+            err.to_string(),
+            "Failed to compile fragment shader\n\
+             common.glsl:4: error C1008: undefined variable \"d\"\n 2 | float b;\n 3 | float c;\n>4 | float d;"
+        );
+    }
+
+    fn hash_of(key: &VariantKey) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn variant_key_ignores_define_order() {
+        let key = VariantKey::new(&[LIGHT_SPOT, USE_SPECULAR_MAP]);
+        let reordered = VariantKey::new(&[USE_SPECULAR_MAP, LIGHT_SPOT, LIGHT_SPOT]);
+        assert_eq!(key, reordered);
+        assert_eq!(hash_of(&key), hash_of(&reordered));
+        assert_eq!(key.defines(), [LIGHT_SPOT, USE_SPECULAR_MAP]);
+    }
+
+    #[test]
+    fn variant_key_differs_for_other_defines() {
+        let key = VariantKey::new(&[LIGHT_SPOT, USE_SPECULAR_MAP]);
+        for other in [
+            VariantKey::new(&[LIGHT_SPOT]),
+            VariantKey::new(&[LIGHT_DIRECTIONAL, USE_SPECULAR_MAP]),
+            VariantKey::default(),
+        ] {
+            assert_ne!(key, other);
+        }
+        let mut variants = std::collections::HashMap::new();
+        variants.insert(key.clone(), 1);
+        variants.insert(VariantKey::new(&[USE_SPECULAR_MAP, LIGHT_SPOT]), 2);
+        variants.insert(VariantKey::new(&[LIGHT_SPOT]), 3);
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[&key], 2);
+        assert_eq!(variants[&VariantKey::new(&[LIGHT_SPOT])], 3);
+    }
+}
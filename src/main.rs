@@ -6,155 +6,232 @@ use learnopengl::*;
 const SCREEN_WIDTH: u32 = 800;
 const SCREEN_HEIGHT: u32 = 600;
 const ENABLE_POLYGON_MODE: bool = false;
+// Number of samples per pixel, overridden by --msaa or $LEARNOPENGL_MSAA
+// (1, 2, 4 or 8)
+const DEFAULT_MSAA_SAMPLES: u32 = 1;
+const MSAA_ENV_VAR: &str = "LEARNOPENGL_MSAA";
+const SCREENSHOT_DIR: &str = "screenshots";
+
+const USAGE: &str = "usage: learnopengl [--headless] [--frames N] [--scene NAME] \
+                     [--output FILE.png] [--gl-version MAJOR.MINOR] [--msaa N]";
+
+struct Args {
+    // render offscreen without a window and exit, for CI
+    headless: bool,
+    frames: u32,
+    scene: String,
+    // PNG of the last headless frame
+    output: Option<std::path::PathBuf>,
+    // requested context version, 4.3 or newer enables compute shaders
+    gl_version: (i32, i32),
+    // samples per pixel, falls back to $LEARNOPENGL_MSAA
+    msaa: Option<u32>,
+}
+
+fn parse_msaa(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|x| MSAA_SAMPLE_COUNTS.contains(x))
+        .ok_or_else(|| {
+            format!(
+                "Invalid MSAA sample count {value}, expected one of {:?}",
+                MSAA_SAMPLE_COUNTS
+            )
+        })
+}
 
-unsafe fn pre_render() {
-    gl::ClearColor(0.2, 0.3, 0.3, 1.0);
-    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        headless: false,
+        frames: 60,
+        scene: SCENE_NAMES[0].to_owned(),
+        output: None,
+        gl_version: (3, 3),
+        msaa: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--headless" => args.headless = true,
+            "--frames" => {
+                args.frames = value()?
+                    .parse()
+                    .map_err(|e| format!("Invalid frame count: {e}"))?
+            }
+            "--scene" => args.scene = value()?,
+            "--output" => args.output = Some(value()?.into()),
+            "--gl-version" => {
+                let version = value()?;
+                args.gl_version = version
+                    .split_once('.')
+                    .and_then(|(major, minor)| Some((major.parse().ok()?, minor.parse().ok()?)))
+                    .ok_or_else(|| format!("Invalid OpenGL version {version}, expected e.g. 4.3"))?
+            }
+            "--msaa" => args.msaa = Some(parse_msaa(&value()?)?),
+            _ => return Err(format!("Unknown argument {arg}\n{USAGE}")),
+        }
+    }
+    Ok(args)
 }
 
 fn main() {
-    let program_start_time = std::time::Instant::now();
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let gl_attr = video_subsystem.gl_attr();
-    gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
-    gl_attr.set_context_version(3, 3);
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(2);
+    });
+    let msaa_samples = match (args.msaa, std::env::var(MSAA_ENV_VAR)) {
+        (Some(samples), _) => samples,
+        (None, Ok(value)) => parse_msaa(&value).unwrap_or_else(|e| {
+            eprintln!("${MSAA_ENV_VAR}: {e}");
+            std::process::exit(2);
+        }),
+        (None, Err(_)) => DEFAULT_MSAA_SAMPLES,
+    };
+
+    if args.headless {
+        if let Err(e) = run_headless(&args, msaa_samples) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    } else {
+        run_window(&args, msaa_samples);
+    }
+}
+
+#[cfg(feature = "headless")]
+fn run_headless(args: &Args, msaa_samples: u32) -> Result<(), String> {
+    let (major, minor) = args.gl_version;
+    let _context = unsafe { HeadlessContext::new(major, minor)? };
+    let mut scene = unsafe { load_scene(&args.scene)? };
+    let formats = [PixelFormat::RGBA8];
+    let target = unsafe {
+        Framebuffer::new_multisampled(
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+            msaa_samples,
+            &formats,
+            DepthAttachment::Renderbuffer,
+        )?
+    };
+
+    let mut camera = Camera::new();
+    camera.set_position(glam::vec3(0.0, 0.0, 3.0));
+    let aspect_ratio = SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32;
+    let start = std::time::Instant::now();
+    unsafe {
+        scene.finish_loading();
+        target.bind();
+        for frame in 0..args.frames {
+            // fixed 60 fps steps so runs are reproducible
+            scene.draw(&camera, frame as f32 / 60.0, aspect_ratio)?;
+        }
+        target.unbind();
+        gl::Finish();
+    }
+    println!(
+        "Rendered {} frames of {} in {:.2?}",
+        args.frames,
+        args.scene,
+        start.elapsed()
+    );
+
+    if let Some(path) = &args.output {
+        let pixels = unsafe {
+            if target.samples > 1 {
+                let resolved =
+                    Framebuffer::new(SCREEN_WIDTH, SCREEN_HEIGHT, &formats, DepthAttachment::None)?;
+                target.resolve(Some(&resolved))?;
+                resolved.read_pixels(0)?
+            } else {
+                target.read_pixels(0)?
+            }
+        };
+        image::DynamicImage::ImageRgba8(pixels)
+            .into_rgb8()
+            .save(path)
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+        println!("Saved last frame to {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "headless"))]
+fn run_headless(_args: &Args, _msaa_samples: u32) -> Result<(), String> {
+    Err("Built without headless support, run with `--features headless`".to_owned())
+}
 
+fn create_window(
+    video_subsystem: &sdl2::VideoSubsystem,
+) -> Result<(sdl2::video::Window, sdl2::video::GLContext), String> {
     let window = video_subsystem
         .window("LEARNOPENGL", SCREEN_WIDTH, SCREEN_HEIGHT)
         //.resizable()
         .position_centered()
         .opengl()
         .build()
-        .unwrap();
+        .map_err(|e| format!("Failed to create window: {e}"))?;
+    let gl_context = window
+        .gl_create_context()
+        .map_err(|e| format!("Failed to create OpenGL context: {e}"))?;
+    Ok((window, gl_context))
+}
 
-    let _gl_context = window.gl_create_context().unwrap();
+fn run_window(args: &Args, mut msaa_samples: u32) {
+    let program_start_time = std::time::Instant::now();
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let gl_attr = video_subsystem.gl_attr();
+    gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
+    gl_attr.set_context_version(args.gl_version.0 as u8, args.gl_version.1 as u8);
+
+    if msaa_samples > 1 {
+        gl_attr.set_multisample_buffers(1);
+        gl_attr.set_multisample_samples(msaa_samples as u8);
+    }
+
+    let exit_with = |e: String| -> ! {
+        eprintln!("{e}");
+        std::process::exit(1);
+    };
+    let (window, _gl_context) = match create_window(&video_subsystem) {
+        Ok(x) => x,
+        // not every driver/display combination has multisampled visuals
+        Err(e) if msaa_samples > 1 => {
+            eprintln!("Warning: {e}, retrying without multisampling");
+            gl_attr.set_multisample_buffers(0);
+            gl_attr.set_multisample_samples(0);
+            msaa_samples = 1;
+            create_window(&video_subsystem).unwrap_or_else(|e| exit_with(e))
+        }
+        Err(e) => exit_with(e),
+    };
     sdl_context.mouse().show_cursor(false);
     sdl_context.mouse().set_relative_mouse_mode(true);
 
     gl::load_with(|name| video_subsystem.gl_get_proc_address(name) as *const _);
 
-    assert_eq!(gl_attr.context_profile(), sdl2::video::GLProfile::Core);
-    assert_eq!(gl_attr.context_version(), (3, 3));
+    // the attributes are only a request, ask the context what it ended up as
+    let version = unsafe { gl_version() };
+    if version < args.gl_version {
+        exit_with(format!(
+            "OpenGL {}.{} was requested but the driver only provides {}.{}",
+            args.gl_version.0, args.gl_version.1, version.0, version.1
+        ));
+    }
 
     unsafe {
         gl::Viewport(0, 0, SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32);
+        if msaa_samples > 1 {
+            gl::Enable(gl::MULTISAMPLE);
+        }
     }
 
-    // load shaders
-    let box_shader_program = {
-        let vertex_shader_source =
-            std::str::from_utf8(include_bytes!("./shaders/vertex_shader.glsl")).unwrap();
-        let vertex_shader =
-            unsafe { Shader::from_str(vertex_shader_source, ShaderType::VertexShader).unwrap() };
-
-        let fragment_shader_source =
-            std::str::from_utf8(include_bytes!("./shaders/fragment_shader.glsl")).unwrap();
-        let fragment_shader = unsafe {
-            Shader::from_str(fragment_shader_source, ShaderType::FragmentShader).unwrap()
-        };
-
-        // link shaders
-        let shader_program = unsafe { ShaderProgram::new() };
-        unsafe {
-            shader_program.attach_shader(vertex_shader);
-            shader_program.attach_shader(fragment_shader);
-            shader_program
-                .link_program()
-                .expect("Shader linking failed");
-        };
-        shader_program
-    };
-
-    let light_shader_program = {
-        let vertex_shader_source =
-            std::str::from_utf8(include_bytes!("./shaders/vertex_shader_light.glsl")).unwrap();
-        let vertex_shader =
-            unsafe { Shader::from_str(vertex_shader_source, ShaderType::VertexShader).unwrap() };
-
-        let fragment_shader_source =
-            std::str::from_utf8(include_bytes!("./shaders/fragment_shader_light.glsl")).unwrap();
-        let fragment_shader = unsafe {
-            Shader::from_str(fragment_shader_source, ShaderType::FragmentShader).unwrap()
-        };
-
-        // link shaders
-        let shader_program = unsafe { ShaderProgram::new() };
-        unsafe {
-            shader_program.attach_shader(vertex_shader);
-            shader_program.attach_shader(fragment_shader);
-            shader_program
-                .link_program()
-                .expect("Shader linking failed");
-        };
-        shader_program
-    };
-
-    #[rustfmt::skip]
-    let cube_verts: [f32; 288] = [
-        // positions       // normals        // texture coords
-        -0.5, -0.5, -0.5,  0.0,  0.0, -1.0,  0.0, 0.0,
-         0.5, -0.5, -0.5,  0.0,  0.0, -1.0,  1.0, 0.0,
-         0.5,  0.5, -0.5,  0.0,  0.0, -1.0,  1.0, 1.0,
-         0.5,  0.5, -0.5,  0.0,  0.0, -1.0,  1.0, 1.0,
-        -0.5,  0.5, -0.5,  0.0,  0.0, -1.0,  0.0, 1.0,
-        -0.5, -0.5, -0.5,  0.0,  0.0, -1.0,  0.0, 0.0,
-
-        -0.5, -0.5,  0.5,  0.0,  0.0, 1.0,   0.0, 0.0,
-         0.5, -0.5,  0.5,  0.0,  0.0, 1.0,   1.0, 0.0,
-         0.5,  0.5,  0.5,  0.0,  0.0, 1.0,   1.0, 1.0,
-         0.5,  0.5,  0.5,  0.0,  0.0, 1.0,   1.0, 1.0,
-        -0.5,  0.5,  0.5,  0.0,  0.0, 1.0,   0.0, 1.0,
-        -0.5, -0.5,  0.5,  0.0,  0.0, 1.0,   0.0, 0.0,
-
-        -0.5,  0.5,  0.5, -1.0,  0.0,  0.0,  1.0, 0.0,
-        -0.5,  0.5, -0.5, -1.0,  0.0,  0.0,  1.0, 1.0,
-        -0.5, -0.5, -0.5, -1.0,  0.0,  0.0,  0.0, 1.0,
-        -0.5, -0.5, -0.5, -1.0,  0.0,  0.0,  0.0, 1.0,
-        -0.5, -0.5,  0.5, -1.0,  0.0,  0.0,  0.0, 0.0,
-        -0.5,  0.5,  0.5, -1.0,  0.0,  0.0,  1.0, 0.0,
-
-         0.5,  0.5,  0.5,  1.0,  0.0,  0.0,  1.0, 0.0,
-         0.5,  0.5, -0.5,  1.0,  0.0,  0.0,  1.0, 1.0,
-         0.5, -0.5, -0.5,  1.0,  0.0,  0.0,  0.0, 1.0,
-         0.5, -0.5, -0.5,  1.0,  0.0,  0.0,  0.0, 1.0,
-         0.5, -0.5,  0.5,  1.0,  0.0,  0.0,  0.0, 0.0,
-         0.5,  0.5,  0.5,  1.0,  0.0,  0.0,  1.0, 0.0,
-
-        -0.5, -0.5, -0.5,  0.0, -1.0,  0.0,  0.0, 1.0,
-         0.5, -0.5, -0.5,  0.0, -1.0,  0.0,  1.0, 1.0,
-         0.5, -0.5,  0.5,  0.0, -1.0,  0.0,  1.0, 0.0,
-         0.5, -0.5,  0.5,  0.0, -1.0,  0.0,  1.0, 0.0,
-        -0.5, -0.5,  0.5,  0.0, -1.0,  0.0,  0.0, 0.0,
-        -0.5, -0.5, -0.5,  0.0, -1.0,  0.0,  0.0, 1.0,
-
-        -0.5,  0.5, -0.5,  0.0,  1.0,  0.0,  0.0, 1.0,
-         0.5,  0.5, -0.5,  0.0,  1.0,  0.0,  1.0, 1.0,
-         0.5,  0.5,  0.5,  0.0,  1.0,  0.0,  1.0, 0.0,
-         0.5,  0.5,  0.5,  0.0,  1.0,  0.0,  1.0, 0.0,
-        -0.5,  0.5,  0.5,  0.0,  1.0,  0.0,  0.0, 0.0,
-        -0.5,  0.5, -0.5,  0.0,  1.0,  0.0,  0.0, 1.0
-    ];
-
-    #[rustfmt::skip]
-    let cube_postiions: [glam::Vec3; 10] = [
-        glam::vec3( 0.0,  0.0,  0.0),
-        glam::vec3( 2.0,  5.0, -15.0),
-        glam::vec3(-1.5, -2.2, -2.5),
-        glam::vec3(-3.8, -2.0, -12.3),
-        glam::vec3( 2.4, -0.4, -3.5),
-        glam::vec3(-1.7,  3.0, -7.5),
-        glam::vec3( 1.3, -2.0, -2.5),
-        glam::vec3( 1.5,  2.0, -2.5),
-        glam::vec3( 1.5,  0.2, -1.5),
-        glam::vec3(-1.3,  1.0, -1.5)
-    ];
-
-    let vao = unsafe { create_vao(&cube_verts) };
-    let vao_light = unsafe { create_vao(&cube_verts) };
-
-    let texture = unsafe { Texture::new("container2.png").unwrap() };
-    let texture_specular = unsafe { Texture::new("container2_specular.png").unwrap() };
+    let mut scene = unsafe { load_scene(&args.scene) }.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
 
     if ENABLE_POLYGON_MODE {
         unsafe { gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE) }
@@ -162,151 +239,40 @@ fn main() {
 
     unsafe { check_gl_error().unwrap() };
 
-    let light_position = glam::vec3(1.2, 1.0, 2.0);
-    let mut projection: glam::Mat4;
-
-    unsafe {
-        gl::Enable(gl::DEPTH_TEST);
-    }
-
     let mut camera = Camera::new();
     camera.set_position(glam::vec3(0.0, 0.0, 3.0));
     let mut keyboard = Keyboard::new();
     let mut current_frame: std::time::Instant;
     let mut last_frame = std::time::Instant::now();
     let mut delta_time: std::time::Duration;
+    let mut take_screenshot = false;
 
     let mut event_pump = sdl_context.event_pump().unwrap();
     'running: loop {
         current_frame = std::time::Instant::now();
         delta_time = current_frame - last_frame;
         last_frame = current_frame;
-        unsafe {
-            pre_render();
-            box_shader_program.use_program();
-        };
-
-        projection =
-            glam::Mat4::perspective_rh(f32::to_radians(camera.zoom), 800.0 / 600.0, 0.1, 100.0);
-        let view = camera.get_view_matrix();
-
-        // Drawing center cube
-        unsafe {
-            box_shader_program.set_uniform_mat4("view", &view).unwrap();
-            box_shader_program
-                .set_uniform_mat4("projection", &projection)
-                .unwrap();
-            box_shader_program
-                .set_uniform_vec3("light.ambient", 0.2, 0.2, 0.2)
-                .unwrap();
-            box_shader_program
-                .set_uniform_vec3("light.diffuse", 0.5, 0.5, 0.5)
-                .unwrap();
-            box_shader_program
-                .set_uniform_vec3("light.specular", 1.0, 1.0, 1.0)
-                .unwrap();
-            //box_shader_program
-            //    .set_uniform_vec3("light.direction", -0.2, -1.0, -0.3)
-            //    .unwrap();
-            box_shader_program
-                .set_uniform_vec3(
-                    "light.position",
-                    camera.position.x,
-                    camera.position.y,
-                    camera.position.z,
-                )
-                .unwrap();
-            box_shader_program
-                .set_uniform_vec3(
-                    "light.direction",
-                    camera.front.x,
-                    camera.front.y,
-                    camera.front.z,
-                )
-                .unwrap();
-
-            box_shader_program
-                .set_uniform_f32("light.cutOff", 12.5_f32.to_radians().cos())
-                .unwrap();
-            box_shader_program
-                .set_uniform_f32("light.outerCutOff", 17.5_f32.to_radians().cos())
-                .unwrap();
-
-            box_shader_program
-                .set_uniform_f32("light.constant", 1.0)
-                .unwrap();
-            box_shader_program
-                .set_uniform_f32("light.linear", 0.09)
-                .unwrap();
-            box_shader_program
-                .set_uniform_f32("light.quadratic", 0.032)
-                .unwrap();
-
-            box_shader_program
-                .set_uniform_i32("material.diffuse", 0)
-                .unwrap();
-            box_shader_program
-                .set_uniform_i32("material.specular", 1)
-                .unwrap();
-            box_shader_program
-                .set_uniform_f32("material.shininess", 32.0)
-                .unwrap();
-        };
-        unsafe { gl::BindVertexArray(vao) };
-        unsafe {
-            box_shader_program
-                .set_uniform_vec3(
-                    "viewPos",
-                    camera.position.x,
-                    camera.position.y,
-                    camera.position.z,
-                )
-                .unwrap()
-        };
-
-        unsafe { gl::ActiveTexture(gl::TEXTURE0) };
-        unsafe { gl::BindTexture(gl::TEXTURE_2D, texture.id) };
-        unsafe { gl::ActiveTexture(gl::TEXTURE1) };
-        unsafe { gl::BindTexture(gl::TEXTURE_2D, texture_specular.id) };
 
-        for (idx, pos) in cube_postiions.iter().enumerate() {
-            let angle = 20.0 * idx as f32;
-            let rotation =
-                glam::Quat::from_axis_angle(glam::vec3(1.0, 0.3, 0.5).normalize(), angle);
-            let model = glam::Mat4::from_rotation_translation(rotation, *pos);
-            unsafe {
-                box_shader_program
-                    .set_uniform_mat4("model", &model)
-                    .unwrap()
-            };
-            unsafe { gl::DrawArrays(gl::TRIANGLES, 0, 36) };
+        match unsafe { scene.reload_shaders() } {
+            Ok(true) => println!("Reloaded shaders"),
+            Ok(false) => {}
+            Err(e) => eprintln!("{e}"),
         }
-
-        // Drawing light source cube
+        let time = program_start_time.elapsed().as_secs_f32();
         unsafe {
-            light_shader_program.use_program();
-            light_shader_program
-                .set_uniform_mat4("view", &view)
-                .unwrap();
-            light_shader_program
-                .set_uniform_mat4("projection", &projection)
-                .unwrap();
-        };
-        unsafe { gl::BindVertexArray(vao_light) };
-        let model = glam::Mat4::from_scale_rotation_translation(
-            glam::vec3(0.2, 0.2, 0.2),
-            glam::Quat::IDENTITY,
-            light_position,
-        );
-        unsafe {
-            light_shader_program
-                .set_uniform_mat4("model", &model)
+            scene
+                .draw(&camera, time, SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32)
                 .unwrap()
         };
-        unsafe { gl::DrawArrays(gl::TRIANGLES, 0, 36) };
 
-        // clean up
-        unsafe { gl::BindVertexArray(0) };
+        if take_screenshot {
+            take_screenshot = false;
+            let path = std::path::Path::new(SCREENSHOT_DIR).join(screenshot_file_name());
+            match unsafe { save_screenshot(&path) } {
+                Ok(()) => println!("Saved screenshot to {}", path.display()),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
         window.gl_swap_window();
 
         for event in event_pump.poll_iter() {
@@ -355,6 +321,10 @@ fn main() {
                     repeat: _,
                 } => match keycode {
                     Some(sdl2::keyboard::Keycode::Escape) => break 'running,
+                    // picked up before the next swap
+                    Some(sdl2::keyboard::Keycode::F12) => {
+                        take_screenshot = true;
+                    }
                     Some(sdl2::keyboard::Keycode::W) => {
                         keyboard.w = true;
                     }
@@ -1,25 +1,5 @@
 #version 330 core
-struct Material {
-    sampler2D diffuse;
-    sampler2D specular;
-    float shininess;
-};
-
-struct Light {
-    vec3 position;
-    vec3 direction;
-
-    float cutOff;
-    float outerCutOff;
-
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-
-    float constant;
-    float linear;
-    float quadratic;
-};
+#include "common/lighting.glsl"
 
 in vec3 Normal;
 in vec3 FragPos;
@@ -31,13 +11,15 @@ uniform vec3 viewPos;
 uniform Material material;
 uniform Light light;
 
+// Variants: LIGHT_DIRECTIONAL or LIGHT_SPOT instead of a point light, and
+// USE_SPECULAR_MAP to take the specular strength from material.specular
 void main()
 {
-
-
-    vec3 lightDir = normalize(light.position - FragPos); // for point light
-    //vec3 lightDir = normalize(-light.direction); // for directional light
-
+#ifdef LIGHT_DIRECTIONAL
+    vec3 lightDir = normalize(-light.direction);
+#else
+    vec3 lightDir = normalize(light.position - FragPos);
+#endif
 
     vec3 ambient = light.ambient * vec3(texture(material.diffuse, TexCoords));
     vec3 norm = normalize(Normal);
@@ -47,21 +29,29 @@ void main()
     vec3 viewDir = normalize(viewPos - FragPos);
     vec3 reflectDir = reflect(-lightDir, norm);
     float spec = pow(max(dot(viewDir, reflectDir), 0.0), material.shininess);
-    vec3 specular = spec * vec3(texture(material.specular, TexCoords));
+#ifdef USE_SPECULAR_MAP
+    vec3 specular = light.specular * spec * vec3(texture(material.specular, TexCoords));
+#else
+    vec3 specular = light.specular * spec;
+#endif
 
+#ifndef LIGHT_DIRECTIONAL
     float distance = length(light.position - FragPos);
     float attenuation = 1.0 / (light.constant + light.linear * distance + light.quadratic * (distance * distance));
 
     ambient *= attenuation;
     diffuse *= attenuation;
     specular *= attenuation;
+#endif
 
+#ifdef LIGHT_SPOT
     float theta = dot(lightDir, normalize(-light.direction));
     float epsilon   = light.cutOff - light.outerCutOff;
     float intensity = clamp((theta - light.outerCutOff) / epsilon, 0.0, 1.0);
 
     diffuse *= intensity;
     specular *= intensity;
+#endif
 
     vec3 result = ambient + diffuse + specular;
     FragColor = vec4(result, 1.0);
@@ -1,11 +1,10 @@
 #version 330 core
+#include "common/camera.glsl"
 layout (location = 0) in vec3 aPos;
 layout (location = 1) in vec3 aNormal;
 layout (location = 2) in vec2 aTexCoords;
 
 uniform mat4 model;
-uniform mat4 view;
-uniform mat4 projection;
 
 out vec3 Normal;
 out vec3 FragPos;
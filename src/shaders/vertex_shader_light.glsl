@@ -1,11 +1,10 @@
 #version 330 core
+#include "common/camera.glsl"
 layout (location = 0) in vec3 aPos;
 layout (location = 1) in vec2 aTexCoord;
 
 
 uniform mat4 model;
-uniform mat4 view;
-uniform mat4 projection;
 
 
 void main()
@@ -0,0 +1,249 @@
+// Golden image tests, renders scenes offscreen and compares them against the
+// references in tests/golden. Needs EGL, e.g. Mesa llvmpipe on CI:
+//
+//     cargo test --features headless --test golden
+//
+// Set LEARNOPENGL_UPDATE_GOLDEN=1 to rewrite the references after an
+// intended change. Failed cases leave the actual image and a diff next to
+// the other test output in target/.
+#![cfg(feature = "headless")]
+
+use learnopengl::*;
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 400;
+const HEIGHT: u32 = 300;
+const UPDATE_ENV_VAR: &str = "LEARNOPENGL_UPDATE_GOLDEN";
+
+// Drivers are allowed to round differently, a few channel steps of noise is
+// fine but anything structural has to show up in the SSIM
+const MAX_CHANNEL_DIFFERENCE: u8 = 3;
+const MAX_DIFFERING_RATIO: f64 = 0.001;
+const MIN_SSIM: f64 = 0.99;
+
+struct GoldenCase {
+    name: &'static str,
+    scene: &'static str,
+    position: glam::Vec3,
+    yaw: f32,
+    pitch: f32,
+    time: f32,
+}
+
+const CASES: [GoldenCase; 5] = [
+    GoldenCase {
+        name: "containers_front",
+        scene: "containers",
+        position: glam::Vec3::new(0.0, 0.0, 3.0),
+        yaw: -90.0,
+        pitch: 0.0,
+        time: 0.0,
+    },
+    // flashlight cone edge across the near containers
+    GoldenCase {
+        name: "containers_spotlight_edge",
+        scene: "containers",
+        position: glam::Vec3::new(0.5, 0.3, 1.5),
+        yaw: -115.0,
+        pitch: -20.0,
+        time: 0.0,
+    },
+    // far containers, mostly attenuation
+    GoldenCase {
+        name: "containers_distance",
+        scene: "containers",
+        position: glam::Vec3::new(1.0, 1.0, 4.0),
+        yaw: -100.0,
+        pitch: 8.0,
+        time: 0.0,
+    },
+    // LIGHT_DIRECTIONAL variant, no attenuation or cone
+    GoldenCase {
+        name: "containers_sun",
+        scene: "containers_sun",
+        position: glam::Vec3::new(0.0, 0.0, 3.0),
+        yaw: -90.0,
+        pitch: 0.0,
+        time: 0.0,
+    },
+    // point light variant, lit from the light cube
+    GoldenCase {
+        name: "containers_lamp",
+        scene: "containers_lamp",
+        position: glam::Vec3::new(0.0, 0.0, 3.0),
+        yaw: -90.0,
+        pitch: 0.0,
+        time: 0.0,
+    },
+];
+
+struct Comparison {
+    differing_pixels: usize,
+    max_difference: u8,
+    ssim: f64,
+    diff: image::RgbImage,
+}
+
+impl Comparison {
+    fn passed(&self) -> bool {
+        let ratio = self.differing_pixels as f64 / (WIDTH * HEIGHT) as f64;
+        ratio <= MAX_DIFFERING_RATIO && self.ssim >= MIN_SSIM
+    }
+}
+
+fn compare(actual: &image::RgbImage, reference: &image::RgbImage) -> Comparison {
+    let mut differing_pixels = 0;
+    let mut max_difference = 0;
+    // the reference dimmed to gray, pixels over the tolerance in red
+    let diff = image::RgbImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = actual.get_pixel(x, y);
+        let r = reference.get_pixel(x, y);
+        let difference = (0..3).map(|c| a[c].abs_diff(r[c])).max().unwrap();
+        max_difference = max_difference.max(difference);
+        if difference > MAX_CHANNEL_DIFFERENCE {
+            differing_pixels += 1;
+            image::Rgb([255, 0, 0])
+        } else {
+            let gray = (luma(r) / 4.0) as u8;
+            image::Rgb([gray, gray, gray])
+        }
+    });
+    Comparison {
+        differing_pixels,
+        max_difference,
+        ssim: ssim(actual, reference),
+        diff,
+    }
+}
+
+fn luma(pixel: &image::Rgb<u8>) -> f64 {
+    0.2126 * pixel[0] as f64 + 0.7152 * pixel[1] as f64 + 0.0722 * pixel[2] as f64
+}
+
+// Mean structural similarity of the luminance over 8x8 windows, 1.0 means
+// identical
+fn ssim(a: &image::RgbImage, b: &image::RgbImage) -> f64 {
+    const WINDOW: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for wy in (0..a.height() - WINDOW + 1).step_by(WINDOW as usize) {
+        for wx in (0..a.width() - WINDOW + 1).step_by(WINDOW as usize) {
+            let samples: Vec<(f64, f64)> = (0..WINDOW * WINDOW)
+                .map(|i| (wx + i % WINDOW, wy + i / WINDOW))
+                .map(|(x, y)| (luma(a.get_pixel(x, y)), luma(b.get_pixel(x, y))))
+                .collect();
+            let n = samples.len() as f64;
+            let mean_a = samples.iter().map(|s| s.0).sum::<f64>() / n;
+            let mean_b = samples.iter().map(|s| s.1).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for (va, vb) in &samples {
+                var_a += (va - mean_a) * (va - mean_a) / (n - 1.0);
+                var_b += (vb - mean_b) * (vb - mean_b) / (n - 1.0);
+                covariance += (va - mean_a) * (vb - mean_b) / (n - 1.0);
+            }
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+unsafe fn render(case: &GoldenCase) -> Result<image::RgbImage, String> {
+    let mut scene = load_scene(case.scene)?;
+    let target = Framebuffer::new(
+        WIDTH,
+        HEIGHT,
+        &[PixelFormat::RGBA8],
+        DepthAttachment::Renderbuffer,
+    )?;
+
+    let mut camera = Camera::new();
+    camera.set_position(case.position);
+    camera.set_rotation(case.yaw, case.pitch);
+
+    scene.finish_loading();
+    target.bind();
+    scene.draw(&camera, case.time, WIDTH as f32 / HEIGHT as f32)?;
+    target.unbind();
+    let pixels = target.read_pixels(0)?;
+    Ok(image::DynamicImage::ImageRgba8(pixels).into_rgb8())
+}
+
+fn reference_path(case: &GoldenCase) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", case.name))
+}
+
+fn output_path(case: &GoldenCase, suffix: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("golden")
+        .join(format!("{}.{}.png", case.name, suffix))
+}
+
+// One test for all cases, the GL context is only current on its own thread
+#[test]
+fn golden_images() {
+    let _context = unsafe { HeadlessContext::new(3, 3).unwrap() };
+    let update = std::env::var_os(UPDATE_ENV_VAR).is_some();
+
+    let mut failures = Vec::new();
+    for case in &CASES {
+        let actual = unsafe { render(case).unwrap() };
+        let reference_path = reference_path(case);
+        if update {
+            actual.save(&reference_path).unwrap();
+            println!("Updated {}", reference_path.display());
+            continue;
+        }
+
+        let reference = match image::open(&reference_path) {
+            Ok(x) => x.into_rgb8(),
+            Err(e) => {
+                failures.push(format!(
+                    "{}: can't open {} ({}), run with {}=1 to create it",
+                    case.name,
+                    reference_path.display(),
+                    e,
+                    UPDATE_ENV_VAR
+                ));
+                continue;
+            }
+        };
+        if reference.dimensions() != actual.dimensions() {
+            failures.push(format!(
+                "{}: reference is {:?} but the render is {:?}",
+                case.name,
+                reference.dimensions(),
+                actual.dimensions()
+            ));
+            continue;
+        }
+
+        let comparison = compare(&actual, &reference);
+        println!(
+            "{}: {} pixels differ, max difference {}, SSIM {:.4}",
+            case.name, comparison.differing_pixels, comparison.max_difference, comparison.ssim
+        );
+        if !comparison.passed() {
+            let actual_path = output_path(case, "actual");
+            let diff_path = output_path(case, "diff");
+            std::fs::create_dir_all(actual_path.parent().unwrap()).unwrap();
+            actual.save(&actual_path).unwrap();
+            comparison.diff.save(&diff_path).unwrap();
+            failures.push(format!(
+                "{}: {} pixels over the tolerance, SSIM {:.4}, see {} and {}",
+                case.name,
+                comparison.differing_pixels,
+                comparison.ssim,
+                actual_path.display(),
+                diff_path.display()
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}